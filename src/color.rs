@@ -0,0 +1,210 @@
+use crate::palette::Color;
+
+/// Hue in degrees, saturation and value in `0.0..=1.0`.
+pub fn rgb_to_hsv((r, g, b): Color) -> (f32, f32, f32) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta % 6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    let s = if max == 0.0 {
+        0.0
+    } else {
+        delta / max
+    };
+
+    (h, s, max)
+}
+
+/// How the distance between two colours is measured when matching against the palette.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ColorMetric {
+    /// Squared Euclidean distance in sRGB.
+    #[default]
+    Rgb,
+    /// Squared Euclidean distance in linear-light RGB; the default under
+    /// [`Converter::linear`](crate::Converter::linear).
+    LinearRgb,
+    /// sRGB distance weighted by the mean red level ("redmean").
+    Redmean,
+    /// CIELAB ΔE*76, i.e. Euclidean distance in L*a*b*.
+    Cie76,
+    /// CIELAB ΔE*00.
+    Ciede2000,
+    /// Euclidean distance in OKLab.
+    Oklab,
+}
+
+impl ColorMetric {
+    /// Converts `c` into the space [`distance`](Self::distance) works in.
+    ///
+    /// Converting each colour once and comparing many times is what makes the
+    /// perceptual metrics affordable per pixel.
+    pub fn to_space(self, c: Color) -> [f64; 3] {
+        match self {
+            ColorMetric::Rgb | ColorMetric::Redmean => [c.0 as f64, c.1 as f64, c.2 as f64],
+            ColorMetric::LinearRgb => [
+                srgb_to_linear(c.0 as f64 / 255.0) * 255.0,
+                srgb_to_linear(c.1 as f64 / 255.0) * 255.0,
+                srgb_to_linear(c.2 as f64 / 255.0) * 255.0,
+            ],
+            ColorMetric::Cie76 | ColorMetric::Ciede2000 => rgb_to_lab(c),
+            ColorMetric::Oklab => rgb_to_oklab(c),
+        }
+    }
+
+    /// Distance between two colours already converted with [`to_space`](Self::to_space).
+    /// Only the ordering is meaningful; some metrics skip the final square root.
+    pub fn distance(self, a: &[f64; 3], b: &[f64; 3]) -> f64 {
+        let (d0, d1, d2) = (a[0] - b[0], a[1] - b[1], a[2] - b[2]);
+        match self {
+            ColorMetric::Rgb | ColorMetric::LinearRgb | ColorMetric::Cie76 | ColorMetric::Oklab => {
+                d0 * d0 + d1 * d1 + d2 * d2
+            }
+            ColorMetric::Redmean => {
+                let rmean = (a[0] + b[0]) / 2.0;
+                (2.0 + rmean / 256.0) * d0 * d0 + 4.0 * d1 * d1 + (2.0 + (255.0 - rmean) / 256.0) * d2 * d2
+            }
+            ColorMetric::Ciede2000 => ciede2000(a, b),
+        }
+    }
+}
+
+fn clamp_channel(v: i32) -> f64 {
+    v.clamp(0, 255) as f64 / 255.0
+}
+
+/// Decodes one sRGB channel in `0.0..=1.0` to linear light. Values outside the
+/// range are extrapolated rather than clamped, so diffused error survives a round trip.
+pub fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of [`srgb_to_linear`].
+pub fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn linear_rgb((r, g, b): Color) -> (f64, f64, f64) {
+    (
+        srgb_to_linear(clamp_channel(r)),
+        srgb_to_linear(clamp_channel(g)),
+        srgb_to_linear(clamp_channel(b)),
+    )
+}
+
+// D65 white point
+fn rgb_to_lab(c: Color) -> [f64; 3] {
+    let (r, g, b) = linear_rgb(c);
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn rgb_to_oklab(c: Color) -> [f64; 3] {
+    let (r, g, b) = linear_rgb(c);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+// Sharma, Wu & Dalal, "The CIEDE2000 Color-Difference Formula" (2005)
+fn ciede2000(lab1: &[f64; 3], lab2: &[f64; 3]) -> f64 {
+    let [l1, a1, b1] = *lab1;
+    let [l2, a2, b2] = *lab2;
+
+    let c1 = a1.hypot(b1);
+    let c2 = a2.hypot(b2);
+    let c_bar7 = ((c1 + c2) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_bar7 / (c_bar7 + 25f64.powi(7))).sqrt());
+    let a1p = (1.0 + g) * a1;
+    let a2p = (1.0 + g) * a2;
+    let c1p = a1p.hypot(b1);
+    let c2p = a2p.hypot(b2);
+
+    let hue = |b: f64, a: f64| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            b.atan2(a).to_degrees().rem_euclid(360.0)
+        }
+    };
+    let h1p = hue(b1, a1p);
+    let h2p = hue(b2, a2p);
+
+    let dlp = l2 - l1;
+    let dcp = c2p - c1p;
+    let dhp = if c1p * c2p == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let dhp = 2.0 * (c1p * c2p).sqrt() * (dhp / 2.0).to_radians().sin();
+
+    let lp_bar = (l1 + l2) / 2.0;
+    let cp_bar = (c1p + c2p) / 2.0;
+    let hp_bar = if c1p * c2p == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+
+    let t = 1.0 - 0.17 * (hp_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * hp_bar).to_radians().cos()
+        + 0.32 * (3.0 * hp_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * hp_bar - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((hp_bar - 275.0) / 25.0).powi(2)).exp();
+    let cp_bar7 = cp_bar.powi(7);
+    let r_c = 2.0 * (cp_bar7 / (cp_bar7 + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (lp_bar - 50.0).powi(2) / (20.0 + (lp_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * cp_bar;
+    let s_h = 1.0 + 0.015 * cp_bar * t;
+    let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+    let (tl, tc, th) = (dlp / s_l, dcp / s_c, dhp / s_h);
+    (tl * tl + tc * tc + th * th + r_t * tc * th).sqrt()
+}
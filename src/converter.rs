@@ -0,0 +1,268 @@
+use image::{self, RgbImage, RgbaImage};
+
+use crate::color::ColorMetric;
+use crate::dither::{Buffer, Ditherer, ErrorDiffusion, Ordered};
+use crate::error::{Error, Result};
+use crate::lut::{nearest_index, PaletteLut};
+use crate::kernel::{named_kernel, read_offsets, Offset, DEFAULT_OFFSETS_FILE};
+use crate::palette::{read_palette_file, Color, DEFAULT_PALETTE_FILE};
+
+/// Holds a source image, the target palette and the dithered result.
+///
+/// Builder steps take and return `self`, so a conversion reads as one chain.
+#[derive(Default, Debug)]
+pub struct Converter {
+    pub image_org: RgbaImage,
+    pub image_converted: RgbImage,
+    /// Palette index of every dithered pixel, row by row; encoders read from here.
+    pub indexed: Vec<usize>,
+    pub palette: Vec<Color>,
+    pub offsets: Vec<Offset>,
+    pub width: u32,
+    pub height: u32,
+    pub metric: ColorMetric,
+    /// Search the palette through a [`PaletteLut`] while dithering.
+    pub lut: bool,
+    /// Dither in linear light instead of on sRGB-encoded values.
+    pub linear: bool,
+    /// Pixels with alpha below this are not dithered and take `transparent_index`.
+    pub alpha_threshold: u8,
+    pub transparent_index: usize,
+    /// Indices of `palette` entries that pixels are never matched to.
+    pub exclude: Vec<usize>,
+    /// Indices of `palette` entries kept by [`best_subset`](Self::best_subset)
+    /// and [`generate_palette`](Self::generate_palette).
+    pub lock: Vec<usize>,
+    /// Index in the loaded palette of each `palette` entry after
+    /// [`best_subset`](Self::best_subset); empty while the palette is used as loaded.
+    pub subset: Vec<usize>,
+    // set once `metric` is chosen explicitly, after which `linear` leaves it alone
+    metric_chosen: bool,
+    // kept between runs and rebuilt only when the palette, metric or exclusions change
+    lookup: Option<PaletteLut>,
+    // `palette` converted with `metric.to_space`, filled for the length of a run
+    space: Vec<[f64; 3]>,
+}
+
+impl Converter {
+    /// Loads [`DEFAULT_PALETTE_FILE`] and [`DEFAULT_OFFSETS_FILE`].
+    pub fn new() -> Result<Converter> {
+        Converter::with_files(DEFAULT_PALETTE_FILE, DEFAULT_OFFSETS_FILE)
+    }
+
+    /// Loads the palette, with its excluded and locked entries, and the error
+    /// diffusion offsets from the given TOML files.
+    pub fn with_files(palette_path: &str, offsets_path: &str) -> Result<Converter> {
+        let palette = read_palette_file(palette_path)?;
+        Ok(Converter::from_parts(palette.colors, read_offsets(offsets_path)?)
+            .exclude(palette.exclude)
+            .lock(palette.lock))
+    }
+
+    /// Builds a converter from an already loaded palette and offsets.
+    pub fn from_parts(palette: Vec<Color>, offsets: Vec<Offset>) -> Converter {
+        Converter {
+            palette,
+            offsets,
+            alpha_threshold: 128,
+            ..Converter::default()
+        }
+    }
+
+    /// Replaces `offsets` with a built-in kernel from [`KERNELS`](crate::kernel::KERNELS).
+    pub fn kernel(mut self, name: &str) -> Result<Self> {
+        self.offsets = named_kernel(name)?;
+        Ok(self)
+    }
+
+    /// Selects how palette distances are measured.
+    pub fn metric(mut self, metric: ColorMetric) -> Self {
+        self.metric = metric;
+        self.metric_chosen = true;
+        self
+    }
+
+    /// Enables the [`PaletteLut`] nearest-colour search for subsequent dithering.
+    pub fn lut(mut self, lut: bool) -> Self {
+        self.lut = lut;
+        self
+    }
+
+    /// Dithers in linear light. Unless a metric was chosen with
+    /// [`metric`](Self::metric), palette entries are then compared with
+    /// [`ColorMetric::LinearRgb`] too.
+    pub fn linear(mut self, linear: bool) -> Self {
+        self.linear = linear;
+        if !self.metric_chosen {
+            self.metric = if linear { ColorMetric::LinearRgb } else { ColorMetric::Rgb };
+        }
+        self
+    }
+
+    /// Sets the alpha below which a pixel counts as transparent; 0 ignores alpha.
+    pub fn alpha_threshold(mut self, alpha_threshold: u8) -> Self {
+        self.alpha_threshold = alpha_threshold;
+        self
+    }
+
+    /// Sets the palette index transparent pixels map to (Picotron sprites use 0).
+    pub fn transparent_index(mut self, transparent_index: usize) -> Self {
+        self.transparent_index = transparent_index;
+        self
+    }
+
+    /// Marks palette entries that pixels are never matched to, e.g. colours
+    /// reserved for UI or transparency.
+    pub fn exclude(mut self, exclude: Vec<usize>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Marks palette entries that are always kept when a subset or generated
+    /// palette is built; lock and exclude an entry to reserve its slot.
+    pub fn lock(mut self, lock: Vec<usize>) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    /// Loads the source image to dither.
+    pub fn read_image(mut self, file_path: &str) -> Result<Self> {
+        let img = image::open(file_path).map_err(|e| Error::Image(file_path.to_string(), e))?;
+        self.image_org = img.to_rgba8();
+        self.width = img.width();
+        self.height = img.height();
+        self.discard_result();
+
+        Ok(self)
+    }
+
+    // every dithering step needs a loaded image and at least one colour to map to
+    pub(crate) fn check_ready(&self) -> Result<()> {
+        if self.palette.is_empty() {
+            return Err(Error::Invalid("palette is empty".to_string()));
+        }
+        if self.transparent_index >= self.palette.len() {
+            return Err(Error::Invalid(format!(
+                "transparent index {} is outside the {}-colour palette",
+                self.transparent_index,
+                self.palette.len()
+            )));
+        }
+        if let Some(&index) = self.exclude.iter().chain(&self.lock).find(|&&i| i >= self.palette.len()) {
+            return Err(Error::Invalid(format!(
+                "palette index {} is outside the {}-colour palette",
+                index,
+                self.palette.len()
+            )));
+        }
+        if (0..self.palette.len()).all(|i| self.exclude.contains(&i)) {
+            return Err(Error::Invalid("every palette entry is excluded".to_string()));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::Invalid("no image loaded".to_string()));
+        }
+        Ok(())
+    }
+
+    // drops the dithered result once `image_org` or `palette` no longer match it
+    pub(crate) fn discard_result(&mut self) {
+        self.indexed.clear();
+        self.image_converted = RgbImage::default();
+    }
+
+    // the encoders need a result for the current image size and palette
+    pub(crate) fn check_dithered(&self) -> Result<()> {
+        if self.indexed.len() != (self.width * self.height) as usize
+            || self.indexed.iter().any(|&i| i >= self.palette.len())
+        {
+            return Err(Error::Invalid("image has not been dithered".to_string()));
+        }
+        Ok(())
+    }
+
+    // whether any pixel of `image_org` takes `transparent_index` instead of being dithered
+    pub(crate) fn has_transparent_pixels(&self) -> bool {
+        self.image_org.pixels().any(|p| p[3] < self.alpha_threshold)
+    }
+
+    /// Index of the palette entry nearest to `pixel` under `metric`, skipping
+    /// `exclude`; ties are broken by HSV distance, then by palette order.
+    pub fn find_closest_palette_index(&self, pixel: Color) -> usize {
+        if !self.space.is_empty() {
+            if let Some(lut) = &self.lookup {
+                return lut.find_closest_palette_index(pixel);
+            }
+            return nearest_index(&self.palette, &self.space, &self.exclude, self.metric, pixel);
+        }
+        let space: Vec<[f64; 3]> = self.palette.iter().map(|&c| self.metric.to_space(c)).collect();
+        nearest_index(&self.palette, &space, &self.exclude, self.metric, pixel)
+    }
+
+    /// Palette entry at [`find_closest_palette_index`](Self::find_closest_palette_index).
+    pub fn find_closest_palette_color(&self, pixel: Color) -> &Color {
+        &self.palette[self.find_closest_palette_index(pixel)]
+    }
+
+    pub(crate) fn idx(&self, x:u32, y:u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    /// Runs `ditherer` over `image_org` and stores the result in `indexed` and `image_converted`.
+    pub fn dither<D: Ditherer + ?Sized>(mut self, ditherer: &D) -> Result<Self> {
+        self.check_ready()?;
+
+        let buf = self.run(ditherer);
+        self.indexed = buf.indices;
+        self.image_converted = self.render();
+
+        Ok(self)
+    }
+
+    // dithers `image_org` to the current palette without touching the stored result
+    pub(crate) fn run<D: Ditherer + ?Sized>(&mut self, ditherer: &D) -> Buffer {
+        if !self.lut {
+            self.lookup = None;
+        } else if !self.lookup.as_ref().is_some_and(|lut| lut.matches(&self.palette, self.metric, &self.exclude)) {
+            self.lookup = Some(PaletteLut::new(&self.palette, self.metric).exclude(self.exclude.clone()));
+        }
+        self.space = self.palette.iter().map(|&c| self.metric.to_space(c)).collect();
+        let mut buf = Buffer::from_image(&self.image_org, self.linear, self.alpha_threshold);
+        for (index, &transparent) in buf.indices.iter_mut().zip(&buf.transparent) {
+            if transparent {
+                *index = self.transparent_index;
+            }
+        }
+        ditherer.dither(self, &mut buf);
+        self.space.clear();
+        buf
+    }
+
+    /// Dithers with the error diffusion kernel in `offsets`.
+    pub fn error_diffusion(self) -> Result<Self> {
+        let ditherer = ErrorDiffusion::new(self.offsets.clone());
+        self.dither(&ditherer)
+    }
+
+    /// Dithers with an 8x8 Bayer matrix at a strength derived from the palette.
+    pub fn bayer(self) -> Result<Self> {
+        self.dither(&Ordered::bayer(8)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_compares_in_linear_light_by_default() {
+        assert_eq!(Converter::default().linear(true).metric, ColorMetric::LinearRgb);
+        assert_eq!(Converter::default().linear(true).linear(false).metric, ColorMetric::Rgb);
+    }
+
+    #[test]
+    fn linear_keeps_a_chosen_metric() {
+        assert_eq!(Converter::default().metric(ColorMetric::Oklab).linear(true).metric, ColorMetric::Oklab);
+        assert_eq!(Converter::default().linear(true).metric(ColorMetric::Rgb).metric, ColorMetric::Rgb);
+        assert_eq!(Converter::default().metric(ColorMetric::Rgb).linear(true).metric, ColorMetric::Rgb);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::error::{Error, Result};
+use crate::rng::SplitMix64;
+
+use super::ThresholdMap;
+
+// Gaussian used to measure how clustered the pattern is around a pixel
+const SIGMA: f64 = 1.5;
+
+// Energy of a binary pattern on a torus: each set pixel adds a Gaussian around itself.
+struct Field {
+    size: usize,
+    pattern: Vec<bool>,
+    energy: Vec<f64>,
+    kernel: Vec<f64>,
+}
+
+impl Field {
+    fn new(size: usize) -> Field {
+        let wrap = |d: usize| d.min(size - d) as f64;
+        let kernel = (0..size * size)
+            .map(|i| {
+                let (dx, dy) = (wrap(i % size), wrap(i / size));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        Field { size, pattern: vec![false; size * size], energy: vec![0.0; size * size], kernel }
+    }
+
+    fn toggle(&mut self, i: usize) {
+        let (x, y) = (i % self.size, i / self.size);
+        let sign = if self.pattern[i] { -1.0 } else { 1.0 };
+        self.pattern[i] = !self.pattern[i];
+        for ny in 0..self.size {
+            for nx in 0..self.size {
+                let dx = (nx + self.size - x) % self.size;
+                let dy = (ny + self.size - y) % self.size;
+                self.energy[ny * self.size + nx] += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+    }
+
+    // set pixel with the most set neighbours
+    fn tightest_cluster(&self) -> usize {
+        (0..self.pattern.len())
+            .filter(|&i| self.pattern[i])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+
+    // unset pixel with the fewest set neighbours
+    fn largest_void(&self) -> usize {
+        (0..self.pattern.len())
+            .filter(|&i| !self.pattern[i])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .unwrap()
+    }
+}
+
+impl ThresholdMap {
+    /// Generates a `size` x `size` blue-noise mask with Ulichney's
+    /// void-and-cluster method. The same `seed` always gives the same mask.
+    pub fn void_and_cluster(size: u32, seed: u64) -> Result<ThresholdMap> {
+        if !(4..=256).contains(&size) {
+            return Err(Error::Invalid(format!("unsupported blue-noise size {} (expected 4 to 256)", size)));
+        }
+        let n = size as usize;
+        let len = n * n;
+        let mut field = Field::new(n);
+
+        // random initial pattern with about a tenth of the pixels set
+        let mut rng = SplitMix64(seed);
+        let ones = (len / 10).max(1);
+        while field.pattern.iter().filter(|&&p| p).count() < ones {
+            let i = (rng.next() % len as u64) as usize;
+            if !field.pattern[i] {
+                field.toggle(i);
+            }
+        }
+
+        // move points from clusters into voids until the pattern is stable
+        for _ in 0..len {
+            let cluster = field.tightest_cluster();
+            field.toggle(cluster);
+            let void = field.largest_void();
+            if void == cluster {
+                field.toggle(void);
+                break;
+            }
+            field.toggle(void);
+        }
+        let initial = field.pattern.clone();
+
+        let mut ranks = vec![0u32; len];
+
+        // phase 1: rank the initial points by removing the tightest cluster first
+        let mut rank = ones;
+        while rank > 0 {
+            rank -= 1;
+            let cluster = field.tightest_cluster();
+            field.toggle(cluster);
+            ranks[cluster] = rank as u32;
+        }
+
+        // phases 2 and 3: restore the initial pattern, then fill the largest void first
+        for (i, &set) in initial.iter().enumerate() {
+            if field.pattern[i] != set {
+                field.toggle(i);
+            }
+        }
+        for rank in ones..len {
+            let void = field.largest_void();
+            field.toggle(void);
+            ranks[void] = rank as u32;
+        }
+
+        Ok(ThresholdMap::from_ranks(size, size, &ranks))
+    }
+
+    /// Loads a threshold mask from a greyscale image, e.g. a published blue-noise
+    /// texture. Pixels are ranked by brightness, so any value range works.
+    pub fn from_image(path: &str) -> Result<ThresholdMap> {
+        let img = image::open(path).map_err(|e| Error::Image(path.to_string(), e))?.to_luma16();
+        let (width, height) = img.dimensions();
+        let mut order: Vec<usize> = (0..img.len()).collect();
+        order.sort_by_key(|&i| img.as_raw()[i]);
+
+        let mut ranks = vec![0u32; order.len()];
+        for (rank, &i) in order.iter().enumerate() {
+            ranks[i] = rank as u32;
+        }
+        Ok(ThresholdMap::from_ranks(width, height, &ranks))
+    }
+}
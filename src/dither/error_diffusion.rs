@@ -0,0 +1,273 @@
+use crate::kernel::Offset;
+use crate::Converter;
+
+use super::{Buffer, Ditherer, Pixel};
+
+/// What happens to kernel taps that fall outside the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BorderPolicy {
+    /// Drop the error carried by out-of-bounds taps.
+    #[default]
+    Discard,
+    /// Move out-of-bounds taps onto the nearest in-bounds pixel; when that one
+    /// has been quantised already, onto the next pixel along the scan, or onto
+    /// the pixel below at the end of a row.
+    Clamp,
+    /// Drop out-of-bounds taps and scale the remaining ones up to the kernel's full weight.
+    Renormalize,
+}
+
+/// Raster-order error diffusion with an arbitrary kernel.
+#[derive(Debug, Clone)]
+pub struct ErrorDiffusion {
+    pub offsets: Vec<Offset>,
+    /// Scan odd rows right to left, mirroring each offset's `dx`.
+    pub serpentine: bool,
+    pub border: BorderPolicy,
+    /// Multiplier applied to the quantisation error before it is spread.
+    pub strength: f64,
+    /// Largest error per channel that is spread, on the `0..=255` scale.
+    pub error_clamp: Option<f64>,
+    /// Errors shorter than this (Euclidean, on the `0..=255` scale) are dropped,
+    /// so flat areas close to a palette colour stay solid.
+    pub error_threshold: f64,
+    /// Taps reaching a pixel whose source luminance differs from the current
+    /// pixel's by more than this are dropped, so error does not cross strong
+    /// edges. Combine with [`BorderPolicy::Renormalize`] to keep the rest of
+    /// the error on the same side of the edge.
+    pub edge_threshold: Option<f64>,
+}
+
+impl ErrorDiffusion {
+    pub fn new(offsets: Vec<Offset>) -> ErrorDiffusion {
+        ErrorDiffusion {
+            offsets,
+            serpentine: false,
+            border: BorderPolicy::default(),
+            strength: 1.0,
+            error_clamp: None,
+            error_threshold: 0.0,
+            edge_threshold: None,
+        }
+    }
+
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+
+    pub fn border(mut self, border: BorderPolicy) -> Self {
+        self.border = border;
+        self
+    }
+
+    pub fn strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn error_clamp(mut self, error_clamp: Option<f64>) -> Self {
+        self.error_clamp = error_clamp;
+        self
+    }
+
+    pub fn error_threshold(mut self, error_threshold: f64) -> Self {
+        self.error_threshold = error_threshold;
+        self
+    }
+
+    pub fn edge_threshold(mut self, edge_threshold: Option<f64>) -> Self {
+        self.edge_threshold = edge_threshold;
+        self
+    }
+
+    // scales, clamps and thresholds the raw quantisation error; `None` means nothing to spread
+    fn limit(&self, error: Pixel) -> Option<Pixel> {
+        let limit = |e: f64| {
+            let e = e * self.strength;
+            match self.error_clamp {
+                Some(c) => e.clamp(-c, c),
+                None => e,
+            }
+        };
+        let error = (limit(error.0), limit(error.1), limit(error.2));
+        let length = (error.0 * error.0 + error.1 * error.1 + error.2 * error.2).sqrt();
+        if length < self.error_threshold || length == 0.0 {
+            None
+        } else {
+            Some(error)
+        }
+    }
+
+    // resolves every kernel tap for the pixel at (x, y) to a buffer index and weight
+    fn targets(&self, buf: &Buffer, edges: &[f64], x: u32, y: u32, reversed: bool, taps: &mut Vec<(usize, f64)>) {
+        taps.clear();
+        let (w, h) = (buf.width as i32, buf.height as i32);
+        let (x, y) = (x as i32, y as i32);
+        let visited = |nx: i32, ny: i32| ny < y || (ny == y && if reversed { nx >= x } else { nx <= x });
+
+        // next pixel along this row's scan, else the one below on the next row
+        let step = if reversed { -1 } else { 1 };
+        let unvisited = |nx: i32| {
+            if (0..w).contains(&(x + step)) {
+                Some((x + step, y))
+            } else if y + 1 < h {
+                Some((nx, y + 1))
+            } else {
+                None
+            }
+        };
+
+        for &(dx, dy, factor) in &self.offsets {
+            let dx = if reversed { -dx } else { dx };
+            let (mut nx, mut ny) = (x + dx, y + dy);
+            if self.border == BorderPolicy::Clamp {
+                nx = nx.clamp(0, w - 1);
+                ny = ny.clamp(0, h - 1);
+                if visited(nx, ny) {
+                    match unvisited(nx) {
+                        Some(next) => (nx, ny) = next,
+                        None => continue,
+                    }
+                }
+            }
+            if nx >= 0 && nx < w && ny >= 0 && ny < h && !visited(nx, ny) {
+                let idx = buf.idx(nx as u32, ny as u32);
+                let across_edge = self.edge_threshold
+                    .is_some_and(|t| (edges[idx] - edges[buf.idx(x as u32, y as u32)]).abs() > t);
+                if !buf.transparent[idx] && !across_edge {
+                    taps.push((idx, factor));
+                }
+            }
+        }
+
+        if self.border == BorderPolicy::Renormalize {
+            let total: f64 = self.offsets.iter().map(|o| o.2).sum();
+            let kept: f64 = taps.iter().map(|t| t.1).sum();
+            if kept != 0.0 {
+                for tap in taps.iter_mut() {
+                    tap.1 *= total / kept;
+                }
+            }
+        }
+    }
+}
+
+impl Ditherer for ErrorDiffusion {
+    fn dither(&self, con: &Converter, buf: &mut Buffer) {
+        let mut taps = Vec::with_capacity(self.offsets.len());
+        // source luminance, captured before any error is added
+        let edges: Vec<f64> = match self.edge_threshold {
+            Some(_) => (0..buf.r.len())
+                .map(|i| 0.299 * buf.r[i] + 0.587 * buf.g[i] + 0.114 * buf.b[i])
+                .collect(),
+            None => Vec::new(),
+        };
+        for y in 0..buf.height {
+            let reversed = self.serpentine && y % 2 == 1;
+            for i in 0..buf.width {
+                let x = if reversed { buf.width - 1 - i } else { i };
+                let idx = buf.idx(x, y);
+                if buf.transparent[idx] {
+                    continue;
+                }
+                let old_pixel = buf.get(idx);
+                let index = con.find_closest_palette_index(buf.to_color(old_pixel));
+                let new_pixel = buf.from_color(con.palette[index]);
+                let error = (
+                    old_pixel.0 - new_pixel.0,
+                    old_pixel.1 - new_pixel.1,
+                    old_pixel.2 - new_pixel.2
+                );
+                buf.put(idx, index, &con.palette);
+
+                let Some(error) = self.limit(error) else {
+                    continue;
+                };
+                self.targets(buf, &edges, x, y, reversed, &mut taps);
+                for &(idx, factor) in &taps {
+                    buf.diffuse(idx, error, factor);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::*;
+    use crate::kernel::named_kernel;
+
+    const WIDTH: u32 = 5;
+    const HEIGHT: u32 = 4;
+
+    // (x, y, reversed, sum of tap weights, every target still unquantised) in scan order
+    fn sums(border: BorderPolicy, serpentine: bool) -> Vec<(u32, u32, bool, f64, bool)> {
+        let ed = ErrorDiffusion::new(named_kernel("floyd-steinberg").unwrap()).border(border).serpentine(serpentine);
+        let buf = Buffer::from_image(&RgbaImage::new(WIDTH, HEIGHT), false, 0);
+        let mut taps = Vec::new();
+        let mut out = Vec::new();
+        for y in 0..HEIGHT {
+            let reversed = serpentine && y % 2 == 1;
+            for i in 0..WIDTH {
+                let x = if reversed { WIDTH - 1 - i } else { i };
+                ed.targets(&buf, &[], x, y, reversed, &mut taps);
+                let later = taps.iter().all(|&(idx, _)| {
+                    let (tx, ty) = (idx as u32 % WIDTH, idx as u32 / WIDTH);
+                    ty > y || (ty == y && if reversed { tx < x } else { tx > x })
+                });
+                out.push((x, y, reversed, taps.iter().map(|t| t.1).sum(), later));
+            }
+        }
+        out
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn discard_drops_out_of_bounds_taps() {
+        for serpentine in [false, true] {
+            for (x, y, reversed, sum, later) in sums(BorderPolicy::Discard, serpentine) {
+                assert!(later);
+                let ahead = if reversed { x > 0 } else { x < WIDTH - 1 };
+                let behind = if reversed { x < WIDTH - 1 } else { x > 0 };
+                let below = y < HEIGHT - 1;
+                let expected = if ahead { 7.0 } else { 0.0 }
+                    + if below { 5.0 } else { 0.0 }
+                    + if below && behind { 3.0 } else { 0.0 }
+                    + if below && ahead { 1.0 } else { 0.0 };
+                assert!(close(sum, expected / 16.0), "({}, {}): {}", x, y, sum);
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_keeps_all_error_until_the_last_pixel() {
+        for serpentine in [false, true] {
+            let sums = sums(BorderPolicy::Clamp, serpentine);
+            let (last, rest) = sums.split_last().unwrap();
+            for &(x, y, _, sum, later) in rest {
+                assert!(later);
+                assert!(close(sum, 1.0), "({}, {}): {}", x, y, sum);
+            }
+            assert!(close(last.3, 0.0));
+        }
+    }
+
+    #[test]
+    fn renormalize_keeps_all_error_until_the_last_pixel() {
+        for serpentine in [false, true] {
+            let sums = sums(BorderPolicy::Renormalize, serpentine);
+            let (last, rest) = sums.split_last().unwrap();
+            for &(x, y, _, sum, later) in rest {
+                assert!(later);
+                assert!(close(sum, 1.0), "({}, {}): {}", x, y, sum);
+            }
+            assert!(close(last.3, 0.0));
+        }
+    }
+}
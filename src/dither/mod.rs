@@ -0,0 +1,125 @@
+//! Dithering algorithms that [`Converter::dither`](crate::Converter::dither) can run.
+
+use image::RgbaImage;
+
+use crate::color::{linear_to_srgb, srgb_to_linear};
+use crate::palette::Color;
+use crate::Converter;
+
+mod blue_noise;
+mod error_diffusion;
+mod ordered;
+mod pattern;
+mod riemersma;
+
+pub use error_diffusion::{BorderPolicy, ErrorDiffusion};
+pub use ordered::{palette_spread, Ordered, ThresholdMap};
+pub use pattern::Pattern;
+pub use riemersma::Riemersma;
+
+/// A working pixel value per channel, on the `0.0..=255.0` scale.
+pub type Pixel = (f64, f64, f64);
+
+/// Working copy of an image as channel planes, so diffused error can push a
+/// value outside `0..=255` before it is quantised.
+///
+/// In linear mode the planes hold linear-light values (still scaled to
+/// `0.0..=255.0`) and error is accumulated there; otherwise they hold sRGB values
+/// and each diffused share is truncated to a whole step.
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    pub width: u32,
+    pub height: u32,
+    pub linear: bool,
+    pub r: Vec<f64>,
+    pub g: Vec<f64>,
+    pub b: Vec<f64>,
+    /// Palette index chosen for each pixel, filled in by [`put`](Self::put).
+    pub indices: Vec<usize>,
+    /// Pixels below the alpha threshold; ditherers skip them and never spread error into them.
+    pub transparent: Vec<bool>,
+}
+
+impl Buffer {
+    pub fn from_image(image: &RgbaImage, linear: bool, alpha_threshold: u8) -> Buffer {
+        let (width, height) = image.dimensions();
+        let len = (width * height) as usize;
+        let mut buf = Buffer {
+            width,
+            height,
+            linear,
+            r: Vec::with_capacity(len),
+            g: Vec::with_capacity(len),
+            b: Vec::with_capacity(len),
+            indices: vec![0; len],
+            transparent: Vec::with_capacity(len),
+        };
+        for pix in image.pixels() {
+            buf.transparent.push(pix[3] < alpha_threshold);
+            let (r, g, b) = buf.from_color((pix[0] as i32, pix[1] as i32, pix[2] as i32));
+            buf.r.push(r);
+            buf.g.push(g);
+            buf.b.push(b);
+        }
+        buf
+    }
+
+    pub fn idx(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    pub fn get(&self, idx: usize) -> Pixel {
+        (self.r[idx], self.g[idx], self.b[idx])
+    }
+
+    pub fn set(&mut self, idx: usize, (r, g, b): Pixel) {
+        self.r[idx] = r;
+        self.g[idx] = g;
+        self.b[idx] = b;
+    }
+
+    /// Converts an sRGB colour into this buffer's working space.
+    pub fn from_color(&self, (r, g, b): Color) -> Pixel {
+        if self.linear {
+            let lin = |v: i32| srgb_to_linear(v as f64 / 255.0) * 255.0;
+            (lin(r), lin(g), lin(b))
+        } else {
+            (r as f64, g as f64, b as f64)
+        }
+    }
+
+    /// Converts a working value back to the nearest sRGB colour for palette lookup.
+    pub fn to_color(&self, (r, g, b): Pixel) -> Color {
+        if self.linear {
+            let enc = |v: f64| (linear_to_srgb(v / 255.0) * 255.0).round() as i32;
+            (enc(r), enc(g), enc(b))
+        } else {
+            (r.round() as i32, g.round() as i32, b.round() as i32)
+        }
+    }
+
+    /// Adds `factor` of `error` to pixel `idx`.
+    pub fn diffuse(&mut self, idx: usize, error: Pixel, factor: f64) {
+        let share = |e: f64| if self.linear { e * factor } else { (e * factor).trunc() };
+        let (dr, dg, db) = (share(error.0), share(error.1), share(error.2));
+        self.r[idx] += dr;
+        self.g[idx] += dg;
+        self.b[idx] += db;
+    }
+
+    /// Records palette entry `index` as the final colour of pixel `idx`.
+    pub fn put(&mut self, idx: usize, index: usize, palette: &[Color]) {
+        self.indices[idx] = index;
+        self.set(idx, self.from_color(palette[index]));
+    }
+}
+
+/// A dithering algorithm.
+///
+/// Implementations choose a `con.palette` entry for every pixel of `buf` that
+/// is not [`transparent`](Buffer::transparent),
+/// normally through [`find_closest_palette_index`](Converter::find_closest_palette_index)
+/// on [`Buffer::to_color`], and record it with [`Buffer::put`].
+pub trait Ditherer {
+    fn dither(&self, con: &Converter, buf: &mut Buffer);
+}
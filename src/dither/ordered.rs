@@ -0,0 +1,138 @@
+use crate::error::{Error, Result};
+use crate::palette::Color;
+use crate::Converter;
+
+use super::{Buffer, Ditherer};
+
+/// A tiled threshold matrix with values in `-0.5..0.5`.
+#[derive(Debug, Clone)]
+pub struct ThresholdMap {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f64>,
+}
+
+impl ThresholdMap {
+    /// Builds a map from ranks `0..width*height`, each used once.
+    pub fn from_ranks(width: u32, height: u32, ranks: &[u32]) -> ThresholdMap {
+        let n = (width * height) as f64;
+        ThresholdMap {
+            width,
+            height,
+            values: ranks.iter().map(|&r| r as f64 / n - 0.5).collect(),
+        }
+    }
+
+    /// The recursive Bayer matrix of `size` x `size`, for sizes 2, 4, 8 and 16.
+    pub fn bayer(size: u32) -> Result<ThresholdMap> {
+        if ![2, 4, 8, 16].contains(&size) {
+            return Err(Error::Invalid(format!("unsupported Bayer size {} (expected 2, 4, 8 or 16)", size)));
+        }
+        let mut ranks = vec![0u32];
+        let mut n = 1;
+        while n < size {
+            let m = n * 2;
+            let mut next = vec![0u32; (m * m) as usize];
+            for y in 0..m {
+                for x in 0..m {
+                    let quadrant = [0, 2, 3, 1][((y / n) * 2 + x / n) as usize];
+                    next[(y * m + x) as usize] = 4 * ranks[((y % n) * n + x % n) as usize] + quadrant;
+                }
+            }
+            ranks = next;
+            n = m;
+        }
+        Ok(ThresholdMap::from_ranks(size, size, &ranks))
+    }
+
+    pub fn at(&self, x: u32, y: u32) -> f64 {
+        self.values[((y % self.height) * self.width + x % self.width) as usize]
+    }
+}
+
+/// Mean distance from each palette colour to its nearest neighbour, which is
+/// roughly how far a threshold has to push a pixel to reach another colour.
+pub fn palette_spread(palette: &[Color]) -> f64 {
+    if palette.len() < 2 {
+        return 0.0;
+    }
+    let total: f64 = palette.iter()
+        .enumerate()
+        .map(|(i, a)| {
+            palette.iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, b)| {
+                    let (dr, dg, db) = ((a.0 - b.0) as f64, (a.1 - b.1) as f64, (a.2 - b.2) as f64);
+                    (dr * dr + dg * dg + db * db).sqrt()
+                })
+                .fold(f64::INFINITY, f64::min)
+        })
+        .sum();
+    total / palette.len() as f64
+}
+
+/// Ordered dithering: each pixel is offset by a tiled threshold before it is
+/// matched against the palette.
+#[derive(Debug, Clone)]
+pub struct Ordered {
+    pub map: ThresholdMap,
+    /// Full spread of the offsets on the `0..=255` scale; `None` derives it
+    /// from the palette with [`palette_spread`].
+    pub strength: Option<f64>,
+}
+
+impl Ordered {
+    pub fn new(map: ThresholdMap) -> Ordered {
+        Ordered { map, strength: None }
+    }
+
+    /// Ordered dithering with a Bayer matrix of `size` x `size`.
+    pub fn bayer(size: u32) -> Result<Ordered> {
+        Ok(Ordered::new(ThresholdMap::bayer(size)?))
+    }
+
+    /// Ordered dithering with a generated `size` x `size` blue-noise mask.
+    pub fn blue_noise(size: u32, seed: u64) -> Result<Ordered> {
+        Ok(Ordered::new(ThresholdMap::void_and_cluster(size, seed)?))
+    }
+
+    pub fn strength(mut self, strength: Option<f64>) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+
+impl Ditherer for Ordered {
+    fn dither(&self, con: &Converter, buf: &mut Buffer) {
+        let strength = self.strength.unwrap_or_else(|| {
+            let working: Vec<Color> = con.palette.iter()
+                .enumerate()
+                .filter(|(i, _)| !con.exclude.contains(i))
+                .map(|(_, &c)| {
+                    let (r, g, b) = buf.from_color(c);
+                    (r.round() as i32, g.round() as i32, b.round() as i32)
+                })
+                .collect();
+            palette_spread(&working)
+        });
+        let rng = |v:f64| -> f64 { v.clamp(0.0, 255.0) };
+
+        for y in 0..buf.height {
+            for x in 0..buf.width {
+                let by = strength * self.map.at(x, y);
+                let idx = buf.idx(x, y);
+                if buf.transparent[idx] {
+                    continue;
+                }
+                let (r, g, b) = buf.get(idx);
+                let r = rng(r + by);
+                let g = rng(g + by);
+                let b = rng(b + by);
+                let index = con.find_closest_palette_index(buf.to_color((r, g, b)));
+
+                buf.put(idx, index, &con.palette);
+            }
+        }
+    }
+}
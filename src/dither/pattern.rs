@@ -0,0 +1,75 @@
+use crate::Converter;
+
+use super::{Buffer, Ditherer, Pixel, ThresholdMap};
+
+/// Thomas Knoll's pattern dithering, as described by Joel Yliluoma.
+///
+/// For every pixel a mix of `candidates` palette entries is built whose average
+/// approximates the source colour, then the threshold map picks one entry from
+/// that mix after sorting it by luminance. Because the mix comes from the
+/// palette itself, irregular palettes dither without the uniform channel offset
+/// that [`Ordered`](super::Ordered) applies.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub map: ThresholdMap,
+    pub candidates: usize,
+    /// How much of the accumulated error is fed back into each attempt.
+    pub error_multiplier: f64,
+}
+
+impl Pattern {
+    pub fn new(map: ThresholdMap) -> Pattern {
+        Pattern { map, candidates: 16, error_multiplier: 1.0 }
+    }
+
+    pub fn candidates(mut self, candidates: usize) -> Self {
+        self.candidates = candidates.max(1);
+        self
+    }
+
+    pub fn error_multiplier(mut self, error_multiplier: f64) -> Self {
+        self.error_multiplier = error_multiplier;
+        self
+    }
+
+    fn mix(&self, con: &Converter, buf: &Buffer, goal: Pixel, mix: &mut Vec<usize>) {
+        mix.clear();
+        let mut error = (0.0, 0.0, 0.0);
+        for _ in 0..self.candidates {
+            let attempt = (
+                goal.0 + error.0 * self.error_multiplier,
+                goal.1 + error.1 * self.error_multiplier,
+                goal.2 + error.2 * self.error_multiplier,
+            );
+            let index = con.find_closest_palette_index(buf.to_color(attempt));
+            let chosen = buf.from_color(con.palette[index]);
+            error.0 += goal.0 - chosen.0;
+            error.1 += goal.1 - chosen.1;
+            error.2 += goal.2 - chosen.2;
+            mix.push(index);
+        }
+        mix.sort_by(|&a, &b| luma(con, a).total_cmp(&luma(con, b)));
+    }
+}
+
+fn luma(con: &Converter, index: usize) -> f64 {
+    let (r, g, b) = con.palette[index];
+    0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+}
+
+impl Ditherer for Pattern {
+    fn dither(&self, con: &Converter, buf: &mut Buffer) {
+        let mut mix = Vec::with_capacity(self.candidates);
+        for y in 0..buf.height {
+            for x in 0..buf.width {
+                let idx = buf.idx(x, y);
+                if buf.transparent[idx] {
+                    continue;
+                }
+                self.mix(con, buf, buf.get(idx), &mut mix);
+                let rank = ((self.map.at(x, y) + 0.5) * mix.len() as f64) as usize;
+                buf.put(idx, mix[rank.min(mix.len() - 1)], &con.palette);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::Converter;
+
+use super::{Buffer, Ditherer};
+
+/// Riemersma dithering: walks the image along a Hilbert curve and spreads error
+/// over the last `history` pixels visited, with weights decaying by `ratio`
+/// from the newest to the oldest.
+///
+/// The curve covers the smallest power-of-two square around the image and is
+/// clipped to it, so any size works.
+#[derive(Debug, Clone)]
+pub struct Riemersma {
+    pub history: usize,
+    pub ratio: f64,
+}
+
+impl Default for Riemersma {
+    fn default() -> Self {
+        Riemersma { history: 16, ratio: 16.0 }
+    }
+}
+
+impl Riemersma {
+    pub fn history(mut self, history: usize) -> Self {
+        self.history = history.max(1);
+        self
+    }
+
+    pub fn ratio(mut self, ratio: f64) -> Self {
+        self.ratio = ratio;
+        self
+    }
+
+    // weights[0] belongs to the oldest error, the newest always weighs 1
+    fn weights(&self) -> Vec<f64> {
+        let q = self.history;
+        if q == 1 {
+            return vec![1.0];
+        }
+        (0..q).map(|i| self.ratio.powf((i as f64) / (q - 1) as f64) / self.ratio).collect()
+    }
+}
+
+// position `d` along the Hilbert curve filling an `n` x `n` square, `n` a power of two
+fn hilbert_d2xy(n: u32, d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = (1 & (t / 2)) as u32;
+        let ry = (1 & (t ^ rx as u64)) as u32;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+impl Ditherer for Riemersma {
+    fn dither(&self, con: &Converter, buf: &mut Buffer) {
+        let weights = self.weights();
+        let mut history = vec![(0.0, 0.0, 0.0); self.history];
+        let n = buf.width.max(buf.height).next_power_of_two();
+
+        for d in 0..(n as u64 * n as u64) {
+            let (x, y) = hilbert_d2xy(n, d);
+            if x >= buf.width || y >= buf.height {
+                continue;
+            }
+            let idx = buf.idx(x, y);
+            if buf.transparent[idx] {
+                continue;
+            }
+            let source = buf.get(idx);
+            let carried = history.iter()
+                .zip(&weights)
+                .fold((0.0, 0.0, 0.0), |acc, (e, w)| (acc.0 + e.0 * w, acc.1 + e.1 * w, acc.2 + e.2 * w));
+            let old_pixel = (source.0 + carried.0, source.1 + carried.1, source.2 + carried.2);
+            let index = con.find_closest_palette_index(buf.to_color(old_pixel));
+            let new_pixel = buf.from_color(con.palette[index]);
+
+            history.rotate_left(1);
+            *history.last_mut().unwrap() = (
+                source.0 - new_pixel.0,
+                source.1 - new_pixel.1,
+                source.2 - new_pixel.2,
+            );
+            buf.put(idx, index, &con.palette);
+        }
+    }
+}
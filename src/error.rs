@@ -0,0 +1,47 @@
+use std::{fmt, io};
+
+/// Everything that can go wrong while loading, dithering or writing an image.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing `path` failed.
+    Io(String, io::Error),
+    /// `path` is not valid TOML or does not have the expected shape.
+    Toml(String, toml::de::Error),
+    /// The image at `path` could not be decoded or encoded.
+    Image(String, image::ImageError),
+    /// The system clipboard could not be opened or written.
+    Clipboard(String),
+    /// The input is well-formed but unusable, e.g. an empty palette.
+    Invalid(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(path, e) => write!(f, "{}: {}", path, e),
+            Error::Toml(path, e) => write!(f, "{}: {}", path, e),
+            Error::Image(path, e) => write!(f, "{}: {}", path, e),
+            Error::Clipboard(e) => write!(f, "clipboard: {}", e),
+            Error::Invalid(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) => Some(e),
+            Error::Toml(_, e) => Some(e),
+            Error::Image(_, e) => Some(e),
+            Error::Clipboard(_) | Error::Invalid(_) => None,
+        }
+    }
+}
+
+/// Reads `path` and parses it as TOML into `T`.
+pub(crate) fn read_toml<T: serde::de::DeserializeOwned>(path: &str) -> Result<T> {
+    let toml_str = std::fs::read_to_string(path).map_err(|e| Error::Io(path.to_string(), e))?;
+    toml::from_str(&toml_str).map_err(|e| Error::Toml(path.to_string(), e))
+}
@@ -0,0 +1,29 @@
+use serde::Deserialize;
+
+use crate::error::{read_toml, Result};
+use crate::preprocess::{Sharpen, Tone};
+
+/// Settings read from a TOML job file, so a conversion can be repeated
+/// without retyping every option.
+///
+/// ```toml
+/// [tone]
+/// auto_levels = true
+/// contrast = 1.2
+/// saturation = 1.4
+///
+/// [sharpen]
+/// radius = 1.0
+/// amount = 0.8
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Job {
+    pub tone: Tone,
+    pub sharpen: Option<Sharpen>,
+}
+
+/// Reads a job file; missing sections keep their defaults.
+pub fn read_job(job_path: &str) -> Result<Job> {
+    read_toml(job_path)
+}
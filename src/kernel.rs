@@ -0,0 +1,122 @@
+use serde::Deserialize;
+
+use crate::error::{read_toml, Error, Result};
+
+/// Offsets file used by [`Converter::new`](crate::Converter::new).
+pub const DEFAULT_OFFSETS_FILE: &str = "def/offset.toml";
+
+/// One error diffusion tap: `(dx, dy, weight)` relative to the current pixel.
+pub type Offset = (i32, i32, f64);
+
+/// A built-in error diffusion kernel; each tap weight is divided by `divisor`.
+#[derive(Debug)]
+pub struct NamedKernel {
+    pub name: &'static str,
+    pub divisor: f64,
+    pub taps: &'static [(i32, i32, f64)],
+}
+
+impl NamedKernel {
+    pub fn offsets(&self) -> Vec<Offset> {
+        self.taps.iter()
+            .map(|&(dx, dy, w)| (dx, dy, w / self.divisor))
+            .collect()
+    }
+}
+
+/// Every kernel selectable by [`named_kernel`].
+pub const KERNELS: &[NamedKernel] = &[
+    NamedKernel {
+        name: "floyd-steinberg",
+        divisor: 16.0,
+        taps: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+    },
+    NamedKernel {
+        name: "false-floyd-steinberg",
+        divisor: 8.0,
+        taps: &[(1, 0, 3.0), (0, 1, 3.0), (1, 1, 2.0)],
+    },
+    NamedKernel {
+        name: "atkinson",
+        divisor: 8.0,
+        taps: &[(1, 0, 1.0), (2, 0, 1.0), (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0), (0, 2, 1.0)],
+    },
+    NamedKernel {
+        name: "jarvis-judice-ninke",
+        divisor: 48.0,
+        taps: &[
+                                          (1, 0, 7.0), (2, 0, 5.0),
+            (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+            (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+        ],
+    },
+    NamedKernel {
+        name: "stucki",
+        divisor: 42.0,
+        taps: &[
+                                          (1, 0, 8.0), (2, 0, 4.0),
+            (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0),
+            (-2, 2, 1.0), (-1, 2, 2.0), (0, 2, 4.0), (1, 2, 2.0), (2, 2, 1.0),
+        ],
+    },
+    NamedKernel {
+        name: "burkes",
+        divisor: 32.0,
+        taps: &[
+                                          (1, 0, 8.0), (2, 0, 4.0),
+            (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0),
+        ],
+    },
+    NamedKernel {
+        name: "sierra",
+        divisor: 32.0,
+        taps: &[
+                                          (1, 0, 5.0), (2, 0, 3.0),
+            (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+                          (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+        ],
+    },
+    NamedKernel {
+        name: "two-row-sierra",
+        divisor: 16.0,
+        taps: &[
+                                          (1, 0, 4.0), (2, 0, 3.0),
+            (-2, 1, 1.0), (-1, 1, 2.0), (0, 1, 3.0), (1, 1, 2.0), (2, 1, 1.0),
+        ],
+    },
+    NamedKernel {
+        name: "sierra-lite",
+        divisor: 4.0,
+        taps: &[(1, 0, 2.0), (-1, 1, 1.0), (0, 1, 1.0)],
+    },
+];
+
+/// Looks up a built-in kernel by name, e.g. `"atkinson"` or `"sierra-lite"`.
+pub fn named_kernel(name: &str) -> Result<Vec<Offset>> {
+    KERNELS.iter()
+        .find(|k| k.name == name)
+        .map(NamedKernel::offsets)
+        .ok_or_else(|| {
+            let names: Vec<&str> = KERNELS.iter().map(|k| k.name).collect();
+            Error::Invalid(format!("unknown kernel '{}' (expected one of: {})", name, names.join(", ")))
+        })
+}
+
+#[derive(Debug, Deserialize)]
+struct Offsets {
+    kernel: Option<String>,
+    offsets: Option<Vec<Offset>>,
+}
+
+/// Reads an error diffusion kernel of the form `offsets = [[dx, dy, weight], ...]`.
+///
+/// The file may name a built-in kernel instead with `kernel = "stucki"`;
+/// an `offsets` table in the same file takes precedence over the name.
+pub fn read_offsets(offsets_path: &str) -> Result<Vec<Offset>> {
+    let offsets: Offsets = read_toml(offsets_path)?;
+    match (offsets.offsets, offsets.kernel) {
+        (Some(offsets), _) => Ok(offsets),
+        (None, Some(name)) => named_kernel(&name),
+        (None, None) => Err(Error::Invalid(format!("{}: expected `offsets` or `kernel`", offsets_path))),
+    }
+}
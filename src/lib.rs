@@ -0,0 +1,41 @@
+//! Dither images down to a fixed palette (PICO-8 / Picotron style) and
+//! encode the result as an image file or a Picotron `userdata()` string.
+//!
+//! ```no_run
+//! use img_::Converter;
+//!
+//! # fn main() -> img_::Result<()> {
+//! let con = Converter::with_files("def/palette.toml", "def/offset.toml")?
+//!     .read_image("input.png")?
+//!     .error_diffusion()?;
+//! con.save("output.png")?;
+//! println!("{}", con.userdata_string()?);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod color;
+mod converter;
+pub mod dither;
+mod error;
+pub mod job;
+pub mod kernel;
+mod lut;
+pub mod output;
+pub mod palette;
+pub mod preprocess;
+pub mod quantize;
+mod rng;
+mod subset;
+
+pub use color::ColorMetric;
+pub use converter::Converter;
+pub use dither::Ditherer;
+pub use error::{Error, Result};
+pub use job::{read_job, Job};
+pub use lut::PaletteLut;
+pub use kernel::{named_kernel, read_offsets, Offset, DEFAULT_OFFSETS_FILE};
+pub use palette::{
+    read_palette, read_palette_file, write_palette, write_palette_file, Color, PaletteFile, PaletteFormat,
+    DEFAULT_PALETTE_FILE,
+};
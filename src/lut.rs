@@ -0,0 +1,230 @@
+use std::ops::Range;
+
+use crate::color::{rgb_to_hsv, ColorMetric};
+use crate::palette::Color;
+
+/// Nearest-colour search for one palette/metric pair.
+///
+/// Everything is built up front: the palette is converted into the metric's
+/// space once and, for the Euclidean metrics, the usable entries are put in a
+/// k-d tree, so a lookup visits a few entries instead of all of them. The
+/// structure is never modified afterwards and can be shared between threads.
+///
+/// Only [`Rgb`](ColorMetric::Rgb), [`LinearRgb`](ColorMetric::LinearRgb),
+/// [`Cie76`](ColorMetric::Cie76) and [`Oklab`](ColorMetric::Oklab) are sped
+/// up, and only on palettes larger than a few dozen entries; `Redmean` and
+/// `Ciede2000` search the whole palette as without it. Results are identical to the plain search in
+/// [`Converter::find_closest_palette_index`](crate::Converter::find_closest_palette_index).
+#[derive(Debug, Clone)]
+pub struct PaletteLut {
+    metric: ColorMetric,
+    palette: Vec<Color>,
+    space: Vec<[f64; 3]>,
+    exclude: Vec<usize>,
+    // usable entries ordered so that the middle of every range is a node splitting on axis `depth % 3`
+    tree: Vec<usize>,
+}
+
+impl PaletteLut {
+    pub fn new(palette: &[Color], metric: ColorMetric) -> PaletteLut {
+        let mut lut = PaletteLut {
+            metric,
+            palette: palette.to_vec(),
+            space: palette.iter().map(|&c| metric.to_space(c)).collect(),
+            exclude: Vec::new(),
+            tree: Vec::new(),
+        };
+        lut.build();
+        lut
+    }
+
+    /// Never answers with the entries at these indices.
+    pub fn exclude(mut self, exclude: Vec<usize>) -> Self {
+        self.exclude = exclude;
+        self.build();
+        self
+    }
+
+    pub fn find_closest_palette_index(&self, pixel: Color) -> usize {
+        if self.tree.is_empty() {
+            return nearest_index(&self.palette, &self.space, &self.exclude, self.metric, pixel);
+        }
+        let target = self.metric.to_space(pixel);
+        let mut best = (f64::INFINITY, 0, false);
+        self.search(0..self.tree.len(), 0, &target, &mut best);
+        match best {
+            (_, index, false) => index,
+            // ties are rare; the plain search breaks them in palette order
+            _ => nearest_index(&self.palette, &self.space, &self.exclude, self.metric, pixel),
+        }
+    }
+
+    // whether this was built for the given palette, metric and excluded entries
+    pub(crate) fn matches(&self, palette: &[Color], metric: ColorMetric, exclude: &[usize]) -> bool {
+        self.metric == metric && self.palette == palette && self.exclude == exclude
+    }
+
+    // only squared Euclidean distances bound an entry by its distance along one axis;
+    // the other metrics leave `tree` empty and fall back to the plain search
+    fn build(&mut self) {
+        self.tree.clear();
+        if !matches!(self.metric, ColorMetric::Rgb | ColorMetric::LinearRgb | ColorMetric::Cie76 | ColorMetric::Oklab) {
+            return;
+        }
+        let mut tree: Vec<usize> = (0..self.palette.len()).filter(|i| !self.exclude.contains(i)).collect();
+        split(&mut tree, &self.space, 0);
+        self.tree = tree;
+    }
+
+    // tracks the smallest distance, its entry and whether another entry ties with it; the far
+    // side is skipped only when it is strictly further than the best so far, so no tie is missed
+    fn search(&self, range: Range<usize>, depth: usize, target: &[f64; 3], best: &mut (f64, usize, bool)) {
+        if range.is_empty() {
+            return;
+        }
+        let mid = range.start + range.len() / 2;
+        let i = self.tree[mid];
+        let distance = self.metric.distance(target, &self.space[i]);
+        if distance < best.0 {
+            *best = (distance, i, false);
+        } else if distance == best.0 {
+            best.2 = true;
+        }
+
+        let axis = depth % 3;
+        let diff = target[axis] - self.space[i][axis];
+        let (near, far) = if diff < 0.0 { (range.start..mid, mid + 1..range.end) } else { (mid + 1..range.end, range.start..mid) };
+        self.search(near, depth + 1, target, best);
+        if diff * diff <= best.0 {
+            self.search(far, depth + 1, target, best);
+        }
+    }
+}
+
+// sorts `entries` around its median on axis `depth % 3`, then each half on the next axis
+fn split(entries: &mut [usize], space: &[[f64; 3]], depth: usize) {
+    if entries.len() <= 1 {
+        return;
+    }
+    let axis = depth % 3;
+    entries.sort_by(|&a, &b| space[a][axis].total_cmp(&space[b][axis]));
+    let mid = entries.len() / 2;
+    let (below, above) = entries.split_at_mut(mid);
+    split(below, space, depth + 1);
+    split(&mut above[1..], space, depth + 1);
+}
+
+// `space` holds `palette` already converted with `metric.to_space`; entries in `exclude` are skipped
+pub(crate) fn nearest_index(palette: &[Color], space: &[[f64; 3]], exclude: &[usize], metric: ColorMetric, pixel: Color) -> usize {
+    let target = metric.to_space(pixel);
+    let distance = |i: usize| if exclude.contains(&i) { f64::INFINITY } else { metric.distance(&target, &space[i]) };
+    let mut best = (f64::INFINITY, 0, true);
+    for i in 0..space.len() {
+        let d = distance(i);
+        if d < best.0 {
+            best = (d, i, false);
+        } else if d == best.0 {
+            best.2 = true;
+        }
+    }
+    if !best.2 {
+        return best.1;
+    }
+
+    // only ties pay for a second pass
+    let candidates: Vec<usize> = (0..palette.len()).filter(|&i| distance(i) == best.0).collect();
+    tie_break(palette, pixel, candidates)
+}
+
+// picks the candidate closest to `pixel` in HSV; `candidates` are in palette order, which decides what is left
+fn tie_break(palette: &[Color], pixel: Color, mut candidates: Vec<usize>) -> usize {
+    if candidates.len() == 1 {
+        return candidates[0];
+    }
+
+    let (h_pixel, s_pixel, v_pixel) = rgb_to_hsv(pixel);
+    candidates.sort_by_key(|&i| {
+        let (h_palette, s_palette, v_palette) = rgb_to_hsv(palette[i]);
+        let dh = (h_pixel - h_palette).abs() as i64;
+        let ds = (s_pixel - s_palette).abs() as i64;
+        let dv = (v_pixel - v_palette).abs() as i64;
+        dh * dh + ds * ds + dv * dv
+    });
+
+    candidates[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METRICS: [ColorMetric; 6] = [
+        ColorMetric::Rgb,
+        ColorMetric::LinearRgb,
+        ColorMetric::Redmean,
+        ColorMetric::Cie76,
+        ColorMetric::Ciede2000,
+        ColorMetric::Oklab,
+    ];
+
+    // PICO-8 with a duplicate entry and a grey that ties with others
+    fn palette() -> Vec<Color> {
+        vec![
+            (0, 0, 0), (29, 43, 83), (126, 37, 83), (0, 135, 81),
+            (171, 82, 54), (95, 87, 79), (194, 195, 199), (255, 241, 232),
+            (255, 0, 77), (255, 163, 0), (255, 236, 39), (0, 228, 54),
+            (41, 173, 255), (131, 118, 156), (255, 119, 168), (255, 204, 170),
+            (95, 87, 79), (128, 128, 128),
+        ]
+    }
+
+    // every channel from below black to above white, as diffused error produces
+    fn sweep() -> impl Iterator<Item = Color> {
+        let steps = || (-64..=320).step_by(16);
+        steps().flat_map(move |r| steps().flat_map(move |g| steps().map(move |b| (r, g, b))))
+    }
+
+    fn assert_matches_plain_search(palette: &[Color], exclude: &[usize]) {
+        for metric in METRICS {
+            let lut = PaletteLut::new(palette, metric).exclude(exclude.to_vec());
+            let space: Vec<[f64; 3]> = palette.iter().map(|&c| metric.to_space(c)).collect();
+            for pixel in sweep() {
+                assert_eq!(
+                    lut.find_closest_palette_index(pixel),
+                    nearest_index(palette, &space, exclude, metric, pixel),
+                    "{:?} {:?}",
+                    metric,
+                    pixel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_matches_plain_search() {
+        assert_matches_plain_search(&palette(), &[]);
+    }
+
+    #[test]
+    fn lookup_matches_plain_search_with_excluded_entries() {
+        assert_matches_plain_search(&palette(), &[0, 5, 7, 17]);
+    }
+
+    #[test]
+    fn lookup_matches_plain_search_on_palette_grid() {
+        // a regular grid puts many entries at the same distance from a pixel
+        let steps = || (0..=255).step_by(85);
+        let grid: Vec<Color> = steps()
+            .flat_map(|r| steps().flat_map(move |g| steps().map(move |b| (r, g, b))))
+            .collect();
+        assert_matches_plain_search(&grid, &[]);
+        assert_matches_plain_search(&grid, &[21, 42]);
+    }
+
+    #[test]
+    fn lookup_is_shareable() {
+        fn assert_sync<T: Send + Sync>() {}
+        assert_sync::<PaletteLut>();
+        assert_sync::<crate::Converter>();
+    }
+}
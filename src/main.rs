@@ -1,322 +1,533 @@
-use image::{self, RgbImage};
-use serde::{Deserialize, Serialize};
-use std::{fs, error::Error};
-use clipboard::{ClipboardProvider, ClipboardContext};
-
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Colors {
-    palette: Vec<[u8; 3]>,
-}
-#[derive(Debug, Deserialize)]
-struct Offsets {
-    offsets: Vec<(i32, i32, f64)>,
+use img_::dither::{BorderPolicy, ErrorDiffusion, Ordered, Pattern, Riemersma, ThresholdMap};
+use img_::preprocess::{preset, Filter, Resize, ResizeMode, Sharpen, Tone};
+use img_::quantize::{PaletteGen, Quantizer};
+use img_::{named_kernel, read_job, read_palette_file, write_palette_file, Color, ColorMetric, Converter, Ditherer, Error, Job, PaletteFile, DEFAULT_OFFSETS_FILE, DEFAULT_PALETTE_FILE};
+use std::fs;
+
+const USAGE: &str = "\
+Usage: img_ <COMMAND> [OPTIONS] <INPUT>
+
+Commands:
+  dither    dither INPUT and save the result as an image
+  userdata  dither INPUT and emit a Picotron userdata(\"u8\", ...) string
+  preview   dither INPUT and print it to the terminal
+
+Options:
+  -o, --output <PATH>    output file (required for dither; userdata defaults to the clipboard)
+      --clipboard        copy the userdata string to the clipboard
+  -p, --palette <PATH>   palette file: .toml | .gpl | .pal (JASC) | .txt (Paint.NET) | .hex |
+                         .ase | .aco | an image (indexed PNG palette, else the swatch colours
+                         in scan order) [default: def/palette.toml]
+  -k, --offsets <PATH>   error diffusion offsets TOML [default: def/offset.toml]
+      --exclude <I,..>   palette indices never matched to a pixel (added to the palette's own)
+      --lock <I,..>      palette indices always kept by --colors and --subset
+      --colors <N>       generate an N-colour palette from the image instead of --palette
+      --quantizer <Q>    palette generator: median-cut | octree | wu | kmeans [default: wu]
+      --refine <N>       k-means passes over the generated palette [default: 0]
+      --subset <K>       dither with the K palette entries that fit the image best and print
+                         their indices and a PICO-8 pal() remap
+      --write-palette <PATH>  save the palette used; the extension picks the format as for --palette
+      --bayer-size <N>   Bayer matrix size: 2 | 4 | 8 | 16 [default: 8]
+      --candidates <N>   palette entries mixed per pixel by pattern [default: 16]
+      --history <N>      errors remembered along the riemersma curve [default: 16]
+      --noise-size <N>   generated blue-noise mask size [default: 64]
+      --mask <PATH>      greyscale image to use as the blue-noise or pattern mask
+      --seed <N>         seed for generated masks and palettes [default: 0]
+      --strength <S>     ordered dither spread on the 0-255 scale [default: from palette spacing]
+      --serpentine       alternate the error diffusion scan direction every row
+      --border <POLICY>  error diffusion at image edges: discard | clamp | renormalize [default: discard]
+      --diffusion-strength <S>  multiply the diffused error by S [default: 1.0]
+      --error-clamp <E>  clamp the diffused error to +-E per channel
+      --error-threshold <E>
+                         drop errors shorter than E so flat areas stay solid [default: 0]
+      --edge-threshold <E>  stop error diffusion across luminance steps larger than E
+      --kernel <NAME>    built-in error diffusion kernel; --offsets overrides it
+                         floyd-steinberg | false-floyd-steinberg | atkinson |
+                         jarvis-judice-ninke | stucki | burkes | sierra |
+                         two-row-sierra | sierra-lite
+      --size <WxH|PRESET>  resize before dithering; presets: pico8, pico8-sprite, picotron,
+                         picotron-sprite, tic80, tic80-sprite
+      --resize <MODE>    fit | fill | crop [default: fit]
+      --filter <FILTER>  nearest | box | triangle | lanczos3 [default: triangle]
+      --letterbox <COLOR>  fill colour for fit/crop, as #rrggbb or r,g,b [default: #000000]
+      --job <PATH>       TOML job file with a [tone] table; options given here override it
+      --auto-levels      stretch each channel to the full range
+      --equalize         equalise the luminance histogram
+      --brightness <B>   add B (-1.0 to 1.0) of full scale [default: 0]
+      --contrast <C>     scale distance from mid-grey [default: 1.0]
+      --gamma <G>        >1 lifts mid-tones, <1 darkens them [default: 1.0]
+      --saturation <S>   0 is greyscale [default: 1.0]
+      --sharpen <AMOUNT>  unsharp mask before dithering
+      --sharpen-radius <R>  unsharp mask blur radius in pixels [default: 1.0]
+      --sharpen-threshold <T>  smallest difference (0-255) to sharpen [default: 0]
+  -m, --method <METHOD>  error-diffusion | bayer | blue-noise | pattern | riemersma
+                         [default: error-diffusion]
+      --metric <METRIC>  palette distance: rgb | linear-rgb | redmean | cie76 | ciede2000 | oklab
+                         [default: rgb]
+      --linear           dither in linear light (implies --metric linear-rgb unless given)
+      --alpha-threshold <A>  pixels with alpha below A are transparent [default: 128]
+      --transparent-index <I>  palette index for transparent pixels [default: 0]
+      --lut              search the palette with a k-d tree (same output; faster on large palettes
+                         with rgb, linear-rgb, cie76 and oklab)
+  -h, --help             print this help
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Dither,
+    Userdata,
+    Preview,
 }
 
-fn read_offsets(offsets_path: &str) -> Result<Vec<(i32, i32, f64)>, Box<dyn Error>> {
-    let toml_str = fs::read_to_string(offsets_path)?;
-    let offsets: Offsets = toml::from_str(&toml_str)?;
-    let mapped_offsets: Vec<(i32, i32, f64)> = offsets.offsets.iter()
-        .map(|&(x, y, magnification)| (x as i32, y as i32, magnification as f64))
-        .collect();
-    Ok(mapped_offsets)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Method {
+    ErrorDiffusion,
+    Bayer,
+    BlueNoise,
+    Pattern,
+    Riemersma,
 }
 
-
-fn read_palette(palette_path: &str) -> Result<Vec<(i32, i32, i32)>, Box<dyn Error>> {
-    let toml_str = fs::read_to_string(palette_path)?;
-    let colors: Colors = toml::from_str(&toml_str)?;
-    let mapped_colors: Vec<(i32, i32, i32)> = colors.palette.iter()
-        .map(|&[r, g, b]| (r as i32, g as i32, b as i32))
-        .collect();
-    Ok(mapped_colors)
+#[derive(Debug)]
+struct Args {
+    command: Command,
+    input: String,
+    output: Option<String>,
+    clipboard: bool,
+    palette: String,
+    exclude: Vec<usize>,
+    lock: Vec<usize>,
+    colors: Option<usize>,
+    quantizer: Quantizer,
+    refine: usize,
+    subset: Option<usize>,
+    write_palette: Option<String>,
+    offsets: Option<String>,
+    kernel: Option<String>,
+    method: Method,
+    serpentine: bool,
+    border: BorderPolicy,
+    metric: ColorMetric,
+    lut: bool,
+    linear: bool,
+    bayer_size: u32,
+    strength: Option<f64>,
+    noise_size: u32,
+    mask: Option<String>,
+    seed: u64,
+    candidates: usize,
+    history: usize,
+    diffusion_strength: f64,
+    error_clamp: Option<f64>,
+    error_threshold: f64,
+    alpha_threshold: u8,
+    transparent_index: usize,
+    size: Option<String>,
+    resize: ResizeMode,
+    filter: Filter,
+    letterbox: Color,
+    job: Option<String>,
+    auto_levels: bool,
+    equalize: bool,
+    brightness: Option<f64>,
+    contrast: Option<f64>,
+    gamma: Option<f64>,
+    saturation: Option<f64>,
+    sharpen: Option<f64>,
+    sharpen_radius: Option<f64>,
+    sharpen_threshold: Option<u8>,
+    edge_threshold: Option<f64>,
 }
 
-pub fn set_clipboard(text: &str) {
-    let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-    ctx.set_contents(text.to_owned()).unwrap();
+fn number<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for {}", value, name))
 }
 
-fn rgb_to_hsv((r, g, b): (i32, i32, i32)) -> (f32, f32, f32) {
-    let r = r as f32 / 255.0;
-    let g = g as f32 / 255.0;
-    let b = b as f32 / 255.0;
-
-    let max = r.max(g).max(b);
-    let min = r.min(g).min(b);
-    let delta = max - min;
-
-    let h = if delta == 0.0 {
-        0.0
-    } else if max == r {
-        60.0 * ((g - b) / delta % 6.0)
-    } else if max == g {
-        60.0 * ((b - r) / delta + 2.0)
-    } else {
-        60.0 * ((r - g) / delta + 4.0)
-    };
-
-    let s = if max == 0.0 {
-        0.0
-    } else {
-        delta / max
-    };
-
-    (h, s, max)
+// comma-separated palette indices such as "0,7,8"
+fn indices(name: &str, value: String) -> Result<Vec<usize>, String> {
+    value.split(',').map(|i| number(name, i.trim().to_string())).collect()
 }
 
-
-#[derive(Default, Debug)]
-pub struct Converter {
-    pub image_org: RgbImage,
-    pub image_converted: RgbImage,
-    pub palette: Vec<(i32, i32, i32)>,
-    pub offsets: Vec<(i32, i32, f64)>,
-    pub width: u32,
-    pub height: u32,
-
+fn parse_color(value: &str) -> Result<Color, String> {
+    let invalid = || format!("invalid colour '{}' (expected #rrggbb or r,g,b)", value);
+    if let Some(hex) = value.strip_prefix('#') {
+        let n = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        if hex.len() != 6 {
+            return Err(invalid());
+        }
+        return Ok(((n >> 16) as i32 & 0xff, (n >> 8) as i32 & 0xff, n as i32 & 0xff));
+    }
+    let parts: Vec<i32> = value.split(',')
+        .map(|p| p.trim().parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    match parts[..] {
+        [r, g, b] if [r, g, b].iter().all(|c| (0..=255).contains(c)) => Ok((r, g, b)),
+        _ => Err(invalid()),
+    }
 }
 
-impl Converter {
-    pub fn new() -> Converter {
-        let p = match read_palette("def/palette.toml") {
-            Ok(colors) => colors,
-            Err(e) => {
-                println!("error by readeing palette file : {}", e);
-                vec![(0, 0, 0)]
-            }
-        };
-        let o = match read_offsets("def/offset.toml") {
-            Ok(ofs) => ofs,
-            Err(e) => {
-                println!("error by reading offset file : {}", e);
-                vec![(0, 0, 0.0)]
-            }
-        };
-
-        let con = Converter {
-            palette: p,
-            offsets: o,
-            ..Converter::default()
-        };
-        con
+// "128x128" or a preset name such as "pico8"
+fn parse_size(value: &str) -> img_::Result<(u32, u32)> {
+    if let Some((w, h)) = value.split_once('x') {
+        if let (Ok(w), Ok(h)) = (w.parse(), h.parse()) {
+            return Ok((w, h));
+        }
     }
+    preset(value)
+}
 
-    pub fn read_image(mut self, file_path: &str) -> Self {
-        let img = image::open(file_path).unwrap();
-        self.image_org = img.to_rgb8();
-        self.width = img.width();
-        self.height = img.height();
-
-        self
-    }
+fn parse_args(mut argv: impl Iterator<Item = String>) -> Result<Args, String> {
+    let command = match argv.next().as_deref() {
+        Some("dither") => Command::Dither,
+        Some("userdata") => Command::Userdata,
+        Some("preview") => Command::Preview,
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+        None => return Err("missing command".to_string()),
+    };
 
-    fn userdata(&self) {
-    
-        let mut buf = "".to_string();
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let pix = self.image_converted.get_pixel(x, y);
-                let r = pix[0] as i32;
-                let g = pix[1] as i32;
-                let b = pix[2] as i32;
-                buf = format!("{}{:02x}", buf, self.find_closest_palette_index((r, g, b)));
+    let mut input = None;
+    let mut output = None;
+    let mut clipboard = false;
+    let mut palette = DEFAULT_PALETTE_FILE.to_string();
+    let mut exclude = Vec::new();
+    let mut lock = Vec::new();
+    let mut colors = None;
+    let mut quantizer = Quantizer::Wu;
+    let mut refine = 0;
+    let mut subset = None;
+    let mut write_palette = None;
+    let mut offsets = None;
+    let mut kernel = None;
+    let mut method = Method::ErrorDiffusion;
+    let mut serpentine = false;
+    let mut border = BorderPolicy::Discard;
+    let mut metric = None;
+    let mut lut = false;
+    let mut linear = false;
+    let mut bayer_size = 8;
+    let mut strength = None;
+    let mut noise_size = 64;
+    let mut mask = None;
+    let mut seed = 0;
+    let mut candidates = 16;
+    let mut history = 16;
+    let mut diffusion_strength = 1.0;
+    let mut error_clamp = None;
+    let mut error_threshold = 0.0;
+    let mut alpha_threshold = 128;
+    let mut transparent_index = 0;
+    let mut size = None;
+    let mut resize = ResizeMode::Fit;
+    let mut filter = Filter::Triangle;
+    let mut letterbox = (0, 0, 0);
+    let mut job = None;
+    let mut auto_levels = false;
+    let mut equalize = false;
+    let mut brightness = None;
+    let mut contrast = None;
+    let mut gamma = None;
+    let mut saturation = None;
+    let mut sharpen = None;
+    let mut sharpen_radius = None;
+    let mut sharpen_threshold = None;
+    let mut edge_threshold = None;
+
+    while let Some(arg) = argv.next() {
+        let mut value = |name: &str| argv.next().ok_or(format!("missing value for {}", name));
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(value(&arg)?),
+            "--clipboard" => clipboard = true,
+            "-p" | "--palette" => palette = value(&arg)?,
+            "--exclude" => exclude = indices(&arg, value(&arg)?)?,
+            "--lock" => lock = indices(&arg, value(&arg)?)?,
+            "--colors" => colors = Some(number(&arg, value(&arg)?)?),
+            "--quantizer" => {
+                quantizer = match value(&arg)?.as_str() {
+                    "median-cut" => Quantizer::MedianCut,
+                    "octree" => Quantizer::Octree,
+                    "wu" => Quantizer::Wu,
+                    "kmeans" => Quantizer::KMeans,
+                    other => return Err(format!("unknown quantizer '{}'", other)),
+                }
+            }
+            "--refine" => refine = number(&arg, value(&arg)?)?,
+            "--subset" => subset = Some(number(&arg, value(&arg)?)?),
+            "--write-palette" => write_palette = Some(value(&arg)?),
+            "-k" | "--offsets" => offsets = Some(value(&arg)?),
+            "--kernel" => kernel = Some(value(&arg)?),
+            "--serpentine" => serpentine = true,
+            "--lut" => lut = true,
+            "--bayer-size" => bayer_size = number(&arg, value(&arg)?)?,
+            "--noise-size" => noise_size = number(&arg, value(&arg)?)?,
+            "--mask" => mask = Some(value(&arg)?),
+            "--candidates" => candidates = number(&arg, value(&arg)?)?,
+            "--history" => history = number(&arg, value(&arg)?)?,
+            "--diffusion-strength" => diffusion_strength = number(&arg, value(&arg)?)?,
+            "--error-clamp" => error_clamp = Some(number(&arg, value(&arg)?)?),
+            "--error-threshold" => error_threshold = number(&arg, value(&arg)?)?,
+            "--alpha-threshold" => alpha_threshold = number(&arg, value(&arg)?)?,
+            "--transparent-index" => transparent_index = number(&arg, value(&arg)?)?,
+            "--size" => size = Some(value(&arg)?),
+            "--resize" => {
+                resize = match value(&arg)?.as_str() {
+                    "fit" => ResizeMode::Fit,
+                    "fill" => ResizeMode::Fill,
+                    "crop" => ResizeMode::Crop,
+                    other => return Err(format!("unknown resize mode '{}'", other)),
+                }
+            }
+            "--filter" => {
+                filter = match value(&arg)?.as_str() {
+                    "nearest" => Filter::Nearest,
+                    "box" => Filter::Box,
+                    "triangle" => Filter::Triangle,
+                    "lanczos3" => Filter::Lanczos3,
+                    other => return Err(format!("unknown filter '{}'", other)),
+                }
+            }
+            "--letterbox" => letterbox = parse_color(&value(&arg)?)?,
+            "--job" => job = Some(value(&arg)?),
+            "--auto-levels" => auto_levels = true,
+            "--equalize" => equalize = true,
+            "--brightness" => brightness = Some(number(&arg, value(&arg)?)?),
+            "--contrast" => contrast = Some(number(&arg, value(&arg)?)?),
+            "--gamma" => gamma = Some(number(&arg, value(&arg)?)?),
+            "--saturation" => saturation = Some(number(&arg, value(&arg)?)?),
+            "--sharpen" => sharpen = Some(number(&arg, value(&arg)?)?),
+            "--sharpen-radius" => sharpen_radius = Some(number(&arg, value(&arg)?)?),
+            "--sharpen-threshold" => sharpen_threshold = Some(number(&arg, value(&arg)?)?),
+            "--edge-threshold" => edge_threshold = Some(number(&arg, value(&arg)?)?),
+            "--seed" => seed = number(&arg, value(&arg)?)?,
+            "--strength" => strength = Some(number(&arg, value(&arg)?)?),
+            "--linear" => linear = true,
+            "--metric" => {
+                metric = Some(match value(&arg)?.as_str() {
+                    "rgb" => ColorMetric::Rgb,
+                    "linear-rgb" => ColorMetric::LinearRgb,
+                    "redmean" => ColorMetric::Redmean,
+                    "cie76" => ColorMetric::Cie76,
+                    "ciede2000" => ColorMetric::Ciede2000,
+                    "oklab" => ColorMetric::Oklab,
+                    other => return Err(format!("unknown metric '{}'", other)),
+                })
+            }
+            "--border" => {
+                border = match value(&arg)?.as_str() {
+                    "discard" => BorderPolicy::Discard,
+                    "clamp" => BorderPolicy::Clamp,
+                    "renormalize" => BorderPolicy::Renormalize,
+                    other => return Err(format!("unknown border policy '{}'", other)),
+                }
             }
+            "-m" | "--method" => {
+                method = match value(&arg)?.as_str() {
+                    "error-diffusion" => Method::ErrorDiffusion,
+                    "bayer" => Method::Bayer,
+                    "blue-noise" => Method::BlueNoise,
+                    "pattern" => Method::Pattern,
+                    "riemersma" => Method::Riemersma,
+                    other => return Err(format!("unknown method '{}'", other)),
+                }
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if input.is_none() => input = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
         }
-
-        //println!("{:?}", buf);
-        set_clipboard(&format!("userdata(\"u8\", {}, {}, \"{}\")", self.width, self.height, buf));
     }
 
-    fn find_closest_palette_index(&self, pixel: (i32, i32, i32)) -> usize {
-        self.palette.iter()
-            .enumerate()
-            .min_by_key(|&(_, &(pr, pg, pb))| {
-                let dr = pixel.0 - pr;
-                let dg = pixel.1 - pg;
-                let db = pixel.2 - pb;
-                (dr * dr + dg * dg + db * db) as i64
-            })
-            .unwrap()
-            .0
+    let metric = metric.unwrap_or(if linear { ColorMetric::LinearRgb } else { ColorMetric::Rgb });
+    let input = input.ok_or("missing INPUT")?;
+    if command == Command::Dither && output.is_none() {
+        return Err("dither needs --output".to_string());
     }
 
-    fn find_closest_palette_color(&self, pixel: (i32, i32, i32)) -> &(i32, i32, i32) {
-        let min_distance = self.palette.iter()
-            .map(|&(pr, pg, pb)| {
-                let dr = (pixel.0 - pr) as i64;
-                let dg = (pixel.1 - pg) as i64;
-                let db = (pixel.2 - pb) as i64;
-                (dr * dr + dg * dg + db * db) as i64
-            })
-            .min()
-            .unwrap();
-
-        let mut candidates: Vec<&(i32, i32, i32)> = self.palette.iter()
-            .filter(|&&(pr, pg, pb)| {
-                let dr = (pixel.0 - pr) as i64;
-                let dg = (pixel.1 - pg) as i64;
-                let db = (pixel.2 - pb) as i64;
-                (dr * dr + dg * dg + db * db) as i64 == min_distance
-            })
-            .collect();
-
-        if candidates.len() == 1 {
-            return candidates[0];
-        }
-
-        candidates.sort_by_key(|&&(pr, pg, pb)| {
-            let (h_pixel, s_pixel, v_pixel) = rgb_to_hsv(pixel);
-            let (h_palette, s_palette, v_palette) = rgb_to_hsv((pr, pg, pb));
-            let dh = (h_pixel - h_palette).abs() as i64;
-            let ds = (s_pixel - s_palette).abs() as i64;
-            let dv = (v_pixel - v_palette).abs() as i64;
-            dh * dh + ds * ds + dv * dv
-        });
+    Ok(Args {
+        command,
+        input,
+        output,
+        clipboard,
+        palette,
+        exclude,
+        lock,
+        colors,
+        quantizer,
+        refine,
+        subset,
+        write_palette,
+        offsets,
+        kernel,
+        method,
+        serpentine,
+        border,
+        metric,
+        lut,
+        linear,
+        bayer_size,
+        strength,
+        noise_size,
+        mask,
+        seed,
+        candidates,
+        history,
+        diffusion_strength,
+        error_clamp,
+        error_threshold,
+        alpha_threshold,
+        transparent_index,
+        size,
+        resize,
+        filter,
+        letterbox,
+        job,
+        auto_levels,
+        equalize,
+        brightness,
+        contrast,
+        gamma,
+        saturation,
+        sharpen,
+        sharpen_radius,
+        sharpen_threshold,
+        edge_threshold,
+    })
+}
 
-        candidates[0]
+fn main() {
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    if argv.is_empty() || argv.iter().any(|a| a == "-h" || a == "--help") {
+        print!("{}", USAGE);
+        return;
     }
+    let args = match parse_args(argv.into_iter()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    fn idx(&self, x:u32, y:u32) -> usize {
-        (y * self.width + x) as usize
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
+}
 
-    pub fn error_diffusion(mut self) -> Self {
-        // make buffer
-        let mut r_buf: Vec<i32> = Vec::new();
-        let mut g_buf: Vec<i32> = Vec::new();
-        let mut b_buf: Vec<i32> = Vec::new();
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let pix = self.image_org.get_pixel(x, y);
-                r_buf.push(pix[0] as i32);
-                g_buf.push(pix[1] as i32);
-                b_buf.push(pix[2] as i32);
-            }
+fn run(args: &Args) -> img_::Result<()> {
+    let con = match (&args.offsets, &args.kernel) {
+        (None, Some(kernel)) => {
+            let palette = read_palette_file(&args.palette)?;
+            Converter::from_parts(palette.colors, named_kernel(kernel)?)
+                .exclude(palette.exclude)
+                .lock(palette.lock)
         }
-        // working
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let idx = self.idx(x, y);
-                let old_pixel = (r_buf[idx], g_buf[idx], b_buf[idx]);
-                let new_pixel = self.find_closest_palette_color(old_pixel);
-                let error = (
-                    old_pixel.0 - new_pixel.0, 
-                    old_pixel.1 - new_pixel.1, 
-                    old_pixel.2 - new_pixel.2
-                );
-                r_buf[idx] = new_pixel.0;
-                g_buf[idx] = new_pixel.1;
-                b_buf[idx] = new_pixel.2;
-
-                for &(dx, dy, factor) in &self.offsets {
-                    let nx = (x as i32 + dx) as u32;
-                    let ny = (y as i32 + dy) as u32;
-                    if nx > 0 && nx < self.width-1 && ny > 0 && ny < self.height-1 {
-                        let idx = self.idx(nx, ny);
-                        r_buf[idx] += (error.0 as f64 * factor) as i32;
-                        g_buf[idx] += (error.1 as f64 * factor) as i32;
-                        b_buf[idx] += (error.2 as f64 * factor) as i32;
-                    }
-                }
-            }
-        }
-        // create new image
-        self.image_converted = RgbImage::new(self.width, self.height);
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let idx = self.idx(x, y);
-                let r = r_buf[idx] as u8;
-                let g = g_buf[idx] as u8;
-                let b = b_buf[idx] as u8;
-                self.image_converted.put_pixel(x, y, image::Rgb([r, g, b]));
-            }
+        (offsets, _) => Converter::with_files(&args.palette, offsets.as_deref().unwrap_or(DEFAULT_OFFSETS_FILE))?,
+    };
+    let exclude = [con.exclude.clone(), args.exclude.clone()].concat();
+    let lock = [con.lock.clone(), args.lock.clone()].concat();
+    let con = con
+        .exclude(exclude)
+        .lock(lock)
+        .metric(args.metric)
+        .lut(args.lut)
+        .linear(args.linear)
+        .alpha_threshold(args.alpha_threshold)
+        .transparent_index(args.transparent_index)
+        .read_image(&args.input)?;
+    let con = match &args.size {
+        Some(size) => {
+            let (width, height) = parse_size(size)?;
+            con.resize(&Resize::new(width, height).mode(args.resize).filter(args.filter).letterbox(args.letterbox))?
         }
+        None => con,
+    };
 
-        self
+    let job = match &args.job {
+        Some(path) => read_job(path)?,
+        None => Job::default(),
+    };
+    let mut tone = job.tone;
+    tone.auto_levels |= args.auto_levels;
+    tone.equalize |= args.equalize;
+    tone.brightness = args.brightness.unwrap_or(tone.brightness);
+    tone.contrast = args.contrast.unwrap_or(tone.contrast);
+    tone.gamma = args.gamma.unwrap_or(tone.gamma);
+    tone.saturation = args.saturation.unwrap_or(tone.saturation);
+    let con = if tone != Tone::default() { con.tone(&tone)? } else { con };
+
+    let mut sharpen = job.sharpen;
+    if args.sharpen.is_some() || args.sharpen_radius.is_some() || args.sharpen_threshold.is_some() {
+        let s = sharpen.get_or_insert_with(Sharpen::default);
+        s.amount = args.sharpen.unwrap_or(s.amount);
+        s.radius = args.sharpen_radius.unwrap_or(s.radius);
+        s.threshold = args.sharpen_threshold.unwrap_or(s.threshold);
     }
+    let con = match &sharpen {
+        Some(sharpen) => con.sharpen(sharpen)?,
+        None => con,
+    };
+    let con = match args.colors {
+        Some(colors) => {
+            let gen = PaletteGen::new(colors).quantizer(args.quantizer).refine(args.refine).seed(args.seed);
+            con.generate_palette(&gen)?
+        }
+        None => con,
+    };
 
-
-    pub fn bayer(mut self) -> Self {
-        let bayer:Vec<u8> = vec![ 
-             0, 32,  8, 40,  2, 34, 10, 42,  48, 16, 56, 24, 50, 18, 58, 26,
-            12, 44,  4, 36, 14, 46,  6, 38,  60, 28, 52, 20, 62, 30, 54, 22,
-             3, 35, 11, 43,  1, 33,  9, 41,  51, 19, 59, 27, 49, 17, 57, 25,
-            15, 47,  7, 39, 13, 45,  5, 37,  63, 31, 55, 23, 61, 29, 53, 21];
-
-        let bayer_idx = |x:u32, y:u32| -> usize {((y % 8) * 8 + (x % 8)).try_into().unwrap()};
-        let rng = |v:i32| -> i32 { v.clamp(0, 255) };
-
-        // make buffer
-        let mut r_buf: Vec<i32> = Vec::new();
-        let mut g_buf: Vec<i32> = Vec::new();
-        let mut b_buf: Vec<i32> = Vec::new();
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let pix = self.image_org.get_pixel(x, y);
-                r_buf.push(pix[0] as i32);
-                g_buf.push(pix[1] as i32);
-                b_buf.push(pix[2] as i32);
-            }
+    let ditherer: Box<dyn Ditherer> = match args.method {
+        Method::ErrorDiffusion => {
+            let ditherer = ErrorDiffusion::new(con.offsets.clone())
+                .serpentine(args.serpentine)
+                .border(args.border)
+                .strength(args.diffusion_strength)
+                .error_clamp(args.error_clamp)
+                .error_threshold(args.error_threshold)
+                .edge_threshold(args.edge_threshold);
+            Box::new(ditherer)
+        }
+        Method::Bayer => Box::new(Ordered::bayer(args.bayer_size)?.strength(args.strength)),
+        Method::BlueNoise => {
+            let map = match &args.mask {
+                Some(path) => ThresholdMap::from_image(path)?,
+                None => ThresholdMap::void_and_cluster(args.noise_size, args.seed)?,
+            };
+            Box::new(Ordered::new(map).strength(args.strength))
         }
+        Method::Pattern => {
+            let map = match &args.mask {
+                Some(path) => ThresholdMap::from_image(path)?,
+                None => ThresholdMap::bayer(args.bayer_size)?,
+            };
+            Box::new(Pattern::new(map).candidates(args.candidates))
+        }
+        Method::Riemersma => Box::new(Riemersma::default().history(args.history)),
+    };
 
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let by = bayer[bayer_idx(x, y)] as i32;
-                let idx = self.idx(x, y);
-                let r = r_buf[idx] as i32;
-                let g = g_buf[idx] as i32;
-                let b = b_buf[idx] as i32; 
-                let r = rng(r + by - 32);
-                let g = rng(g + by - 32);
-                let b = rng(b + by - 32);
-                let (r, g, b) = self.find_closest_palette_color((r, g, b)); 
+    let con = match args.subset {
+        Some(k) => con.best_subset(k, ditherer.as_ref())?,
+        None => con,
+    };
+    if let Some(path) = &args.write_palette {
+        let palette = PaletteFile { colors: con.palette.clone(), exclude: con.exclude.clone(), lock: con.lock.clone() };
+        write_palette_file(path, &palette)?;
+    }
+    let con = con.dither(ditherer.as_ref())?;
 
-                r_buf[idx] = *r;
-                g_buf[idx] = *g;
-                b_buf[idx] = *b;
+    match args.command {
+        Command::Dither => con.save(args.output.as_deref().unwrap())?,
+        Command::Userdata => {
+            if let Some(path) = &args.output {
+                fs::write(path, con.userdata_string()?).map_err(|e| Error::Io(path.clone(), e))?;
             }
-        }
-
-        self.image_converted = RgbImage::new(self.width, self.height);
-        for y in 0..self.height {
-            for x in 0..self.width {
-                let idx = self.idx(x, y);
-                let r = r_buf[idx] as u8;
-                let g = g_buf[idx] as u8;
-                let b = b_buf[idx] as u8;
-                self.image_converted.put_pixel(x, y, image::Rgb([r, g, b]));
+            if args.clipboard || args.output.is_none() {
+                con.userdata()?;
             }
         }
-
-        self
+        Command::Preview => print!("{}", con.preview()?),
     }
-
-    pub fn save(&self, save_file_path: &str) {
-        self.image_converted.save(save_file_path).unwrap();    
+    if args.subset.is_some() {
+        let indices: Vec<String> = con.subset.iter().map(|i| i.to_string()).collect();
+        println!("-- palette indices: {}", indices.join(","));
+        println!("{}", con.pal_string());
     }
+    Ok(())
 }
-
-
-
-const IMAGE_FILE: &str = "onarimon.jpg";
-
-fn main() {
-
-    Converter::new().read_image(IMAGE_FILE)
-        .error_diffusion()
-        //.save("output.png");
-        .userdata();
-
-    /*
-    Converter::new().read_image(IMAGE_FILE)
-        .bayer()
-        .save("bayer.png");
-    */
-
-}
-
-
-
-
-
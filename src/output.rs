@@ -0,0 +1,136 @@
+use clipboard::{ClipboardContext, ClipboardProvider};
+use image::{RgbImage, RgbaImage};
+
+use crate::error::{Error, Result};
+use crate::Converter;
+
+/// Copies `text` to the system clipboard.
+pub fn set_clipboard(text: &str) -> Result<()> {
+    let mut ctx: ClipboardContext = ClipboardProvider::new().map_err(|e| Error::Clipboard(e.to_string()))?;
+    ctx.set_contents(text.to_owned()).map_err(|e| Error::Clipboard(e.to_string()))
+}
+
+impl Converter {
+    /// Encodes the dithered image as palette indices in a Picotron `userdata("u8", w, h, "...")` literal.
+    pub fn userdata_string(&self) -> Result<String> {
+        self.check_dithered()?;
+        let mut buf = String::with_capacity((self.width * self.height * 2) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                buf.push_str(&format!("{:02x}", self.indexed[self.idx(x, y)]));
+            }
+        }
+
+        Ok(format!("userdata(\"u8\", {}, {}, \"{}\")", self.width, self.height, buf))
+    }
+
+    /// Copies [`userdata_string`](Self::userdata_string) to the clipboard.
+    pub fn userdata(&self) -> Result<()> {
+        set_clipboard(&self.userdata_string()?)
+    }
+
+    /// PICO-8 call that shows the palette picked by [`best_subset`](Self::best_subset):
+    /// screen slot `i` displays loaded entry `subset[i]`, with entries 16-31 taken
+    /// as the extended colours 128-143.
+    pub fn pal_string(&self) -> String {
+        let colors: Vec<String> = self.subset.iter()
+            .map(|&i| if i < 16 { i } else { i - 16 + 128 }.to_string())
+            .collect();
+        format!("pal({{[0]={}}}, 1)", colors.join(","))
+    }
+
+    /// Renders the dithered image with 24-bit ANSI colours, two pixels per character cell.
+    pub fn preview(&self) -> Result<String> {
+        self.check_dithered()?;
+        let mut buf = String::new();
+        for y in (0..self.height).step_by(2) {
+            for x in 0..self.width {
+                let top = self.palette[self.indexed[self.idx(x, y)]];
+                if y + 1 < self.height {
+                    let bottom = self.palette[self.indexed[self.idx(x, y + 1)]];
+                    buf.push_str(&format!(
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                        top.0, top.1, top.2, bottom.0, bottom.1, bottom.2
+                    ));
+                } else {
+                    buf.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[49m\u{2580}", top.0, top.1, top.2));
+                }
+            }
+            buf.push_str("\x1b[0m\n");
+        }
+        Ok(buf)
+    }
+
+    /// Renders `indexed` through `palette` as an RGB image.
+    ///
+    /// This and the other encoders fail until the current image has been
+    /// dithered to the current palette.
+    pub fn indexed_image(&self) -> Result<RgbImage> {
+        self.check_dithered()?;
+        Ok(self.render())
+    }
+
+    // `indexed_image` for a result known to be current
+    pub(crate) fn render(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let (r, g, b) = self.palette[self.indexed[self.idx(x, y)]];
+            image::Rgb([r as u8, g as u8, b as u8])
+        })
+    }
+
+    /// Writes the dithered image; the format follows the file extension.
+    /// Transparent source pixels stay transparent when the image has any.
+    pub fn save(&self, save_file_path: &str) -> Result<()> {
+        let image = self.indexed_image()?;
+        let transparent = |x, y| self.image_org.get_pixel(x, y)[3] < self.alpha_threshold;
+        let result = if (0..self.height).any(|y| (0..self.width).any(|x| transparent(x, y))) {
+            RgbaImage::from_fn(self.width, self.height, |x, y| {
+                let [r, g, b] = image.get_pixel(x, y).0;
+                image::Rgba([r, g, b, if transparent(x, y) { 0 } else { 255 }])
+            })
+            .save(save_file_path)
+        } else {
+            image.save(save_file_path)
+        };
+        result.map_err(|e| Error::Image(save_file_path.to_string(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocess::Resize;
+    use crate::quantize::PaletteGen;
+
+    fn converter() -> Converter {
+        let mut con = Converter::from_parts(vec![(0, 0, 0), (255, 255, 255)], Vec::new());
+        con.image_org = RgbaImage::from_fn(4, 2, |x, _| image::Rgba([x as u8 * 80, 0, 0, 255]));
+        (con.width, con.height) = con.image_org.dimensions();
+        con
+    }
+
+    fn assert_not_dithered(con: &Converter) {
+        for result in [con.userdata_string().map(|_| ()), con.preview().map(|_| ()), con.indexed_image().map(|_| ())] {
+            assert!(matches!(result, Err(Error::Invalid(msg)) if msg == "image has not been dithered"));
+        }
+    }
+
+    #[test]
+    fn encoders_need_a_dithered_image() {
+        assert_not_dithered(&converter());
+        let con = converter().bayer().unwrap();
+        assert!(con.userdata_string().unwrap().starts_with("userdata(\"u8\", 4, 2, \""));
+        assert_eq!(con.indexed_image().unwrap().dimensions(), (4, 2));
+    }
+
+    #[test]
+    fn encoders_reject_a_stale_result() {
+        assert_not_dithered(&converter().bayer().unwrap().resize(&Resize::new(2, 2)).unwrap());
+        assert_not_dithered(&converter().bayer().unwrap().generate_palette(&PaletteGen::new(2)).unwrap());
+
+        // a palette shrunk by hand leaves indices pointing past its end
+        let mut con = converter().bayer().unwrap();
+        con.palette.truncate(0);
+        assert_not_dithered(&con);
+    }
+}
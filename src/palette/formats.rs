@@ -0,0 +1,544 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use image::{ImageFormat, RgbaImage};
+
+use crate::error::{Error, Result};
+
+use super::Color;
+
+/// A palette file format [`read_palette_file`](super::read_palette_file) and
+/// [`write_palette_file`](super::write_palette_file) understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// This crate's `palette = [[r, g, b], ...]` TOML, the only format that keeps `exclude` and `lock`.
+    Toml,
+    /// GIMP `.gpl`.
+    Gpl,
+    /// JASC-PAL `.pal`, as written by Paint Shop Pro and Aseprite.
+    JascPal,
+    /// Paint.NET `.txt`, one `AARRGGBB` per line.
+    PaintNet,
+    /// One `rrggbb` per line, as downloaded from Lospec.
+    Hex,
+    /// Adobe Swatch Exchange `.ase`; only RGB swatches are read, others are skipped.
+    Ase,
+    /// Adobe Color Swatch `.aco`; only RGB swatches are read, others are skipped.
+    Aco,
+    /// An image: the PLTE chunk of an indexed PNG in order, otherwise the
+    /// distinct opaque colours of a swatch image in scan order. Written as a
+    /// one-pixel-high strip.
+    Image,
+}
+
+/// Most distinct colours a swatch image may hold before it is taken for a picture.
+pub const MAX_SWATCH_COLORS: usize = 256;
+
+impl PaletteFormat {
+    /// Picks the format from the file extension.
+    pub fn from_path(path: &str) -> Option<PaletteFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "toml" => Some(PaletteFormat::Toml),
+            "gpl" => Some(PaletteFormat::Gpl),
+            "pal" => Some(PaletteFormat::JascPal),
+            "txt" => Some(PaletteFormat::PaintNet),
+            "hex" => Some(PaletteFormat::Hex),
+            "ase" => Some(PaletteFormat::Ase),
+            "aco" => Some(PaletteFormat::Aco),
+            _ => ImageFormat::from_path(path).ok().filter(|f| f.reading_enabled()).map(|_| PaletteFormat::Image),
+        }
+    }
+
+    /// Recognises the format from the file's contents.
+    pub fn detect(bytes: &[u8]) -> Option<PaletteFormat> {
+        if image::guess_format(bytes).is_ok() {
+            return Some(PaletteFormat::Image);
+        }
+        if bytes.starts_with(b"ASEF") {
+            return Some(PaletteFormat::Ase);
+        }
+        if bytes.starts_with(&[0, 1]) || bytes.starts_with(&[0, 2]) {
+            return Some(PaletteFormat::Aco);
+        }
+        let text = std::str::from_utf8(bytes).ok()?.trim_start_matches('\u{feff}');
+        let first = text.lines().map(str::trim).find(|l| !l.is_empty())?;
+        if first.starts_with("GIMP Palette") {
+            Some(PaletteFormat::Gpl)
+        } else if first.starts_with("JASC-PAL") {
+            Some(PaletteFormat::JascPal)
+        } else if first.starts_with("palette") {
+            Some(PaletteFormat::Toml)
+        } else if first.starts_with(';') || first.trim_start_matches('#').len() == 8 {
+            Some(PaletteFormat::PaintNet)
+        } else {
+            Some(PaletteFormat::Hex)
+        }
+    }
+
+    // decodes every format but `Toml`, which carries more than colours
+    pub(crate) fn parse(self, path: &str, bytes: &[u8]) -> Result<Vec<Color>> {
+        let invalid = |msg: &str| Error::Invalid(format!("{}: {}", path, msg));
+        let text = || std::str::from_utf8(bytes).map_err(|_| invalid("not a text file"));
+        match self {
+            PaletteFormat::Toml => Err(invalid("TOML palettes are read by read_palette_file")),
+            PaletteFormat::Gpl => parse_gpl(text()?).ok_or_else(|| invalid("malformed GIMP palette")),
+            PaletteFormat::JascPal => parse_jasc(text()?).ok_or_else(|| invalid("malformed JASC-PAL palette")),
+            PaletteFormat::PaintNet | PaletteFormat::Hex => {
+                parse_hex_lines(text()?).ok_or_else(|| invalid("expected one rrggbb or aarrggbb colour per line"))
+            }
+            PaletteFormat::Ase => parse_ase(&mut Reader::new(bytes)).map_err(|e| invalid(&e)),
+            PaletteFormat::Aco => parse_aco(&mut Reader::new(bytes)).map_err(|e| invalid(&e)),
+            PaletteFormat::Image => {
+                if let Some(colors) = png_palette(bytes) {
+                    return Ok(colors);
+                }
+                let image = image::load_from_memory(bytes).map_err(|e| Error::Image(path.to_string(), e))?;
+                swatch_colors(&image.to_rgba8()).ok_or_else(|| {
+                    invalid(&format!("more than {} colours; expected a swatch image", MAX_SWATCH_COLORS))
+                })
+            }
+        }
+    }
+
+    // encodes every format but `Toml` and `Image`; `name` ends up where the format has a palette title
+    pub(crate) fn encode(self, name: &str, colors: &[Color]) -> Vec<u8> {
+        let hex = |&(r, g, b): &Color| format!("{:02x}{:02x}{:02x}", r, g, b);
+        match self {
+            PaletteFormat::Toml | PaletteFormat::Image => Vec::new(),
+            PaletteFormat::Gpl => {
+                let mut text = format!("GIMP Palette\nName: {}\nColumns: 8\n#\n", name);
+                for c in colors {
+                    text.push_str(&format!("{:3} {:3} {:3}\t#{}\n", c.0, c.1, c.2, hex(c)));
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::JascPal => {
+                let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", colors.len());
+                for &(r, g, b) in colors {
+                    text.push_str(&format!("{} {} {}\r\n", r, g, b));
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::PaintNet => {
+                let mut text = format!("; paint.net Palette File\n; {}\n", name);
+                for c in colors {
+                    text.push_str(&format!("FF{}\n", hex(c).to_uppercase()));
+                }
+                text.into_bytes()
+            }
+            PaletteFormat::Hex => colors.iter().map(|c| hex(c) + "\n").collect::<String>().into_bytes(),
+            PaletteFormat::Ase => encode_ase(colors),
+            PaletteFormat::Aco => encode_aco(colors),
+        }
+    }
+}
+
+// entries of the PLTE chunk, in order, when `bytes` is an indexed-colour PNG
+fn png_palette(bytes: &[u8]) -> Option<Vec<Color>> {
+    let mut chunks = bytes.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    let mut indexed = false;
+    while chunks.len() >= 12 {
+        let len = u32::from_be_bytes(chunks[..4].try_into().unwrap()) as usize;
+        let data = chunks.get(8..8 + len)?;
+        match &chunks[4..8] {
+            // colour type 3 is indexed
+            b"IHDR" => indexed = data.get(9) == Some(&3),
+            b"PLTE" if indexed => {
+                return Some(data.chunks_exact(3).map(|c| (c[0] as i32, c[1] as i32, c[2] as i32)).collect());
+            }
+            b"IDAT" => return None,
+            _ => {}
+        }
+        chunks = chunks.get(len + 12..)?;
+    }
+    None
+}
+
+// distinct colours of pixels that are not fully transparent, in scan order
+fn swatch_colors(image: &RgbaImage) -> Option<Vec<Color>> {
+    let mut seen = HashSet::new();
+    let mut colors = Vec::new();
+    for p in image.pixels().filter(|p| p[3] > 0) {
+        let color = (p[0] as i32, p[1] as i32, p[2] as i32);
+        if seen.insert(color) {
+            if colors.len() == MAX_SWATCH_COLORS {
+                return None;
+            }
+            colors.push(color);
+        }
+    }
+    Some(colors)
+}
+
+fn channel(value: &str) -> Option<i32> {
+    value.parse().ok().filter(|c| (0..=255).contains(c))
+}
+
+fn parse_gpl(text: &str) -> Option<Vec<Color>> {
+    let mut lines = text.lines().map(str::trim);
+    if !lines.next()?.trim_start_matches('\u{feff}').starts_with("GIMP Palette") {
+        return None;
+    }
+    lines
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("Name:") && !l.starts_with("Columns:"))
+        .map(|l| {
+            let mut fields = l.split_whitespace();
+            Some((channel(fields.next()?)?, channel(fields.next()?)?, channel(fields.next()?)?))
+        })
+        .collect()
+}
+
+fn parse_jasc(text: &str) -> Option<Vec<Color>> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    if !lines.next()?.trim_start_matches('\u{feff}').starts_with("JASC-PAL") {
+        return None;
+    }
+    lines.next()?;
+    let count: usize = lines.next()?.parse().ok()?;
+    let colors: Vec<Color> = lines
+        .take(count)
+        .map(|l| {
+            let mut fields = l.split_whitespace();
+            Some((channel(fields.next()?)?, channel(fields.next()?)?, channel(fields.next()?)?))
+        })
+        .collect::<Option<_>>()?;
+    (colors.len() == count).then_some(colors)
+}
+
+// Paint.NET and .hex lists; a leading alpha byte is dropped
+fn parse_hex_lines(text: &str) -> Option<Vec<Color>> {
+    text.lines()
+        .map(|l| l.trim().trim_start_matches('\u{feff}'))
+        .filter(|l| !l.is_empty() && !l.starts_with(';'))
+        .map(|l| {
+            let digits = l.trim_start_matches('#');
+            let rgb = match digits.len() {
+                6 => digits,
+                8 => &digits[2..],
+                _ => return None,
+            };
+            let n = u32::from_str_radix(rgb, 16).ok()?;
+            Some(((n >> 16) as i32 & 0xff, (n >> 8) as i32 & 0xff, n as i32 & 0xff))
+        })
+        .collect()
+}
+
+// big-endian cursor over the binary Adobe formats
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> std::result::Result<&'a [u8], String> {
+        let bytes = self.bytes.get(self.pos..self.pos + len).ok_or("unexpected end of file")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> std::result::Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> std::result::Result<f32, String> {
+        Ok(f32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+const ASE_COLOR: u16 = 0x0001;
+
+fn parse_ase(reader: &mut Reader) -> std::result::Result<Vec<Color>, String> {
+    if reader.take(4)? != b"ASEF" {
+        return Err("missing ASEF signature".to_string());
+    }
+    reader.take(4)?;
+    let blocks = reader.u32()?;
+    let mut colors = Vec::new();
+    for _ in 0..blocks {
+        let kind = reader.u16()?;
+        let len = reader.u32()? as usize;
+        let mut block = Reader::new(reader.take(len)?);
+        // group start and end blocks carry no colour
+        if kind != ASE_COLOR {
+            continue;
+        }
+        let name_len = block.u16()? as usize;
+        block.take(name_len * 2)?;
+        // CMYK, LAB and Gray swatches are skipped
+        if block.take(4)? != b"RGB " {
+            continue;
+        }
+        let mut rgb = [0; 3];
+        for c in &mut rgb {
+            *c = (block.f32()?.clamp(0.0, 1.0) * 255.0).round() as i32;
+        }
+        colors.push((rgb[0], rgb[1], rgb[2]));
+    }
+    if colors.is_empty() {
+        return Err("no RGB swatches".to_string());
+    }
+    Ok(colors)
+}
+
+// UTF-16 name with its length (in code units, terminator included) in front
+fn utf16_name(out: &mut Vec<u8>, name: &str) {
+    let units: Vec<u16> = name.encode_utf16().chain([0]).collect();
+    out.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        out.extend_from_slice(&unit.to_be_bytes());
+    }
+}
+
+fn encode_ase(colors: &[Color]) -> Vec<u8> {
+    let mut out = b"ASEF".to_vec();
+    out.extend_from_slice(&[0, 1, 0, 0]);
+    out.extend_from_slice(&(colors.len() as u32).to_be_bytes());
+    for &(r, g, b) in colors {
+        let mut block = Vec::new();
+        utf16_name(&mut block, &format!("#{:02x}{:02x}{:02x}", r, g, b));
+        block.extend_from_slice(b"RGB ");
+        for c in [r, g, b] {
+            block.extend_from_slice(&(c as f32 / 255.0).to_be_bytes());
+        }
+        // global colour
+        block.extend_from_slice(&0u16.to_be_bytes());
+
+        out.extend_from_slice(&ASE_COLOR.to_be_bytes());
+        out.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        out.extend_from_slice(&block);
+    }
+    out
+}
+
+const ACO_RGB: u16 = 0;
+
+// reads the first section; version 2 repeats version 1's colours with names attached
+fn parse_aco(reader: &mut Reader) -> std::result::Result<Vec<Color>, String> {
+    let version = reader.u16()?;
+    if version != 1 && version != 2 {
+        return Err(format!("unknown ACO version {}", version));
+    }
+    let count = reader.u16()?;
+    let mut colors = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let space = reader.u16()?;
+        let (r, g, b) = (reader.u16()?, reader.u16()?, reader.u16()?);
+        reader.u16()?;
+        if version == 2 {
+            let name_len = reader.u32()? as usize;
+            reader.take(name_len * 2)?;
+        }
+        // HSB, CMYK, Lab and grayscale swatches are skipped
+        if space == ACO_RGB {
+            colors.push(((r >> 8) as i32, (g >> 8) as i32, (b >> 8) as i32));
+        }
+    }
+    if colors.is_empty() {
+        return Err("no RGB swatches".to_string());
+    }
+    Ok(colors)
+}
+
+// a version 1 section followed by the same colours as version 2, for Photoshop's swatch names
+fn encode_aco(colors: &[Color]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for version in [1u16, 2] {
+        out.extend_from_slice(&version.to_be_bytes());
+        out.extend_from_slice(&(colors.len() as u16).to_be_bytes());
+        for &(r, g, b) in colors {
+            out.extend_from_slice(&ACO_RGB.to_be_bytes());
+            for c in [r, g, b, 0] {
+                out.extend_from_slice(&(c as u16 * 257).to_be_bytes());
+            }
+            if version == 2 {
+                let units: Vec<u16> = format!("#{:02x}{:02x}{:02x}", r, g, b).encode_utf16().chain([0]).collect();
+                out.extend_from_slice(&(units.len() as u32).to_be_bytes());
+                for unit in units {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLORS: [Color; 4] = [(0, 0, 0), (255, 0, 77), (41, 173, 255), (255, 241, 232)];
+
+    fn ase_block(out: &mut Vec<u8>, kind: u16, block: &[u8]) {
+        out.extend_from_slice(&kind.to_be_bytes());
+        out.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        out.extend_from_slice(block);
+    }
+
+    fn ase_swatch(name: &str, model: &[u8; 4], values: &[f32]) -> Vec<u8> {
+        let mut block = Vec::new();
+        utf16_name(&mut block, name);
+        block.extend_from_slice(model);
+        for v in values {
+            block.extend_from_slice(&v.to_be_bytes());
+        }
+        block.extend_from_slice(&2u16.to_be_bytes());
+        block
+    }
+
+    fn aco_swatch(out: &mut Vec<u8>, version: u16, space: u16, values: [u16; 4]) {
+        out.extend_from_slice(&space.to_be_bytes());
+        for v in values {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+        if version == 2 {
+            out.extend_from_slice(&2u32.to_be_bytes());
+            out.extend_from_slice(&[0, b'x', 0, 0]);
+        }
+    }
+
+    fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        // bitwise CRC-32 over the chunk type and data
+        let mut crc = !0u32;
+        for &byte in kind.iter().chain(data) {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            }
+        }
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&(!crc).to_be_bytes());
+    }
+
+    // 8-bit PNG of `color_type` with an optional PLTE chunk; `rows` are unfiltered samples,
+    // stored in a single uncompressed deflate block
+    fn png(color_type: u8, width: u32, plte: Option<&[Color]>, rows: &[Vec<u8>]) -> Vec<u8> {
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&(rows.len() as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+        png_chunk(&mut out, b"IHDR", &ihdr);
+        if let Some(plte) = plte {
+            let data: Vec<u8> = plte.iter().flat_map(|&(r, g, b)| [r as u8, g as u8, b as u8]).collect();
+            png_chunk(&mut out, b"PLTE", &data);
+        }
+        let raw: Vec<u8> = rows.iter().flat_map(|row| std::iter::once(0).chain(row.iter().copied())).collect();
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in &raw {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(&raw);
+        zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        png_chunk(&mut out, b"IDAT", &zlib);
+        png_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for (path, format) in [
+            ("p.gpl", PaletteFormat::Gpl),
+            ("p.pal", PaletteFormat::JascPal),
+            ("p.txt", PaletteFormat::PaintNet),
+            ("p.hex", PaletteFormat::Hex),
+            ("p.ase", PaletteFormat::Ase),
+            ("p.aco", PaletteFormat::Aco),
+        ] {
+            assert_eq!(PaletteFormat::from_path(path), Some(format));
+            let bytes = format.encode("test", &COLORS);
+            assert_eq!(PaletteFormat::detect(&bytes), Some(format), "{}", path);
+            assert_eq!(format.parse(path, &bytes).unwrap(), COLORS, "{}", path);
+        }
+    }
+
+    #[test]
+    fn ase_skips_groups_and_non_rgb_swatches() {
+        let mut bytes = b"ASEF".to_vec();
+        bytes.extend_from_slice(&[0, 1, 0, 0]);
+        bytes.extend_from_slice(&5u32.to_be_bytes());
+        let mut group = Vec::new();
+        utf16_name(&mut group, "group");
+        ase_block(&mut bytes, 0xc001, &group);
+        ase_block(&mut bytes, ASE_COLOR, &ase_swatch("red", b"RGB ", &[1.0, 0.0, 0.0]));
+        ase_block(&mut bytes, ASE_COLOR, &ase_swatch("cyan", b"CMYK", &[1.0, 0.0, 0.0, 0.0]));
+        ase_block(&mut bytes, ASE_COLOR, &ase_swatch("blue", b"RGB ", &[0.0, 0.0, 1.0]));
+        ase_block(&mut bytes, 0xc002, &[]);
+
+        assert_eq!(PaletteFormat::detect(&bytes), Some(PaletteFormat::Ase));
+        assert_eq!(PaletteFormat::Ase.parse("p.ase", &bytes).unwrap(), [(255, 0, 0), (0, 0, 255)]);
+    }
+
+    #[test]
+    fn aco_reads_the_first_section_and_skips_non_rgb_swatches() {
+        let mut bytes = Vec::new();
+        for version in [1u16, 2] {
+            bytes.extend_from_slice(&version.to_be_bytes());
+            bytes.extend_from_slice(&3u16.to_be_bytes());
+            aco_swatch(&mut bytes, version, ACO_RGB, [0xffff, 0, 0x4d4d, 0]);
+            aco_swatch(&mut bytes, version, 2, [0, 0xffff, 0xffff, 0xffff]);
+            aco_swatch(&mut bytes, version, ACO_RGB, [0x2929, 0xadad, 0xffff, 0]);
+        }
+
+        assert_eq!(PaletteFormat::detect(&bytes), Some(PaletteFormat::Aco));
+        assert_eq!(PaletteFormat::Aco.parse("p.aco", &bytes).unwrap(), [(255, 0, 77), (41, 173, 255)]);
+        // the version 2 section alone
+        let v2 = &bytes[2 + 2 + 3 * 10..];
+        assert_eq!(PaletteFormat::Aco.parse("p.aco", v2).unwrap(), [(255, 0, 77), (41, 173, 255)]);
+    }
+
+    #[test]
+    fn binary_formats_without_rgb_swatches_fail() {
+        let mut ase = b"ASEF".to_vec();
+        ase.extend_from_slice(&[0, 1, 0, 0]);
+        ase.extend_from_slice(&1u32.to_be_bytes());
+        ase_block(&mut ase, ASE_COLOR, &ase_swatch("grey", b"Gray", &[0.5]));
+        assert!(PaletteFormat::Ase.parse("p.ase", &ase).is_err());
+
+        let mut aco = vec![0, 1, 0, 1];
+        aco_swatch(&mut aco, 1, 8, [5000, 0, 0, 0]);
+        assert!(PaletteFormat::Aco.parse("p.aco", &aco).is_err());
+    }
+    #[test]
+    fn indexed_png_reads_the_whole_plte_in_order() {
+        let plte = [(9, 9, 9), (255, 0, 0), (0, 255, 0), (0, 0, 255), (1, 2, 3)];
+        // pixels use the entries in another order and never the last one
+        let bytes = png(3, 4, Some(&plte), &[vec![3, 1, 2, 0], vec![2, 2, 3, 1]]);
+
+        assert_eq!(PaletteFormat::detect(&bytes), Some(PaletteFormat::Image));
+        assert_eq!(PaletteFormat::Image.parse("p.png", &bytes).unwrap(), plte);
+    }
+
+    #[test]
+    fn truecolour_png_ignores_its_suggested_plte() {
+        let suggested = [(1, 1, 1), (2, 2, 2)];
+        let bytes = png(2, 3, Some(&suggested), &[vec![0, 0, 255, 255, 0, 0, 0, 0, 255]]);
+
+        assert_eq!(png_palette(&bytes), None);
+        assert_eq!(PaletteFormat::Image.parse("p.png", &bytes).unwrap(), [(0, 0, 255), (255, 0, 0)]);
+    }
+
+    #[test]
+    fn swatch_strip_keeps_the_first_of_repeated_colours() {
+        let (a, b, c) = ([10, 20, 30, 255], [200, 100, 0, 255], [0, 0, 0, 255]);
+        let clear = [0, 0, 0, 0];
+        let rows = vec![[a, b, a, clear, c, b, c].concat()];
+        let bytes = png(6, 7, None, &rows);
+
+        assert_eq!(PaletteFormat::Image.parse("p.png", &bytes).unwrap(), [(10, 20, 30), (200, 100, 0), (0, 0, 0)]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+mod formats;
+
+pub use formats::{PaletteFormat, MAX_SWATCH_COLORS};
+
+/// Palette file used by [`Converter::new`](crate::Converter::new).
+pub const DEFAULT_PALETTE_FILE: &str = "def/palette.toml";
+
+/// An RGB colour with each channel in `0..=255`.
+pub type Color = (i32, i32, i32);
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Colors {
+    palette: Vec<[u8; 3]>,
+    #[serde(default)]
+    exclude: Vec<usize>,
+    #[serde(default)]
+    lock: Vec<usize>,
+}
+
+/// A palette with the indices of its reserved entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaletteFile {
+    pub colors: Vec<Color>,
+    /// Entries never picked when matching a pixel, e.g. colours kept for UI.
+    pub exclude: Vec<usize>,
+    /// Entries always kept when a subset or generated palette is built.
+    pub lock: Vec<usize>,
+}
+
+/// Reads a palette in any [`PaletteFormat`], e.g. a TOML of the form
+/// `palette = [[r, g, b], ...]`.
+///
+/// Fails if the file is missing, malformed or lists no colours.
+pub fn read_palette(palette_path: &str) -> Result<Vec<Color>> {
+    Ok(read_palette_file(palette_path)?.colors)
+}
+
+/// Reads a palette, taking the format from the extension or, failing that,
+/// from the contents. Only TOML palettes carry `exclude = [...]` and
+/// `lock = [...]` lists of palette indices.
+pub fn read_palette_file(palette_path: &str) -> Result<PaletteFile> {
+    let bytes = std::fs::read(palette_path).map_err(|e| Error::Io(palette_path.to_string(), e))?;
+    let format = PaletteFormat::from_path(palette_path)
+        .or_else(|| PaletteFormat::detect(&bytes))
+        .ok_or_else(|| Error::Invalid(format!("{}: unrecognised palette format", palette_path)))?;
+    let palette = match format {
+        PaletteFormat::Toml => {
+            let colors: Colors = toml::from_str(&String::from_utf8_lossy(&bytes))
+                .map_err(|e| Error::Toml(palette_path.to_string(), e))?;
+            let mapped_colors: Vec<Color> = colors.palette.iter()
+                .map(|&[r, g, b]| (r as i32, g as i32, b as i32))
+                .collect();
+            PaletteFile { colors: mapped_colors, exclude: colors.exclude, lock: colors.lock }
+        }
+        format => PaletteFile { colors: format.parse(palette_path, &bytes)?, ..PaletteFile::default() },
+    };
+
+    if palette.colors.is_empty() {
+        return Err(Error::Invalid(format!("{}: palette is empty", palette_path)));
+    }
+    if let Some(&index) = palette.exclude.iter().chain(&palette.lock).find(|&&i| i >= palette.colors.len()) {
+        return Err(Error::Invalid(format!(
+            "{}: index {} is outside the {}-colour palette",
+            palette_path,
+            index,
+            palette.colors.len()
+        )));
+    }
+    Ok(palette)
+}
+
+/// Writes bare colours; see [`write_palette_file`].
+pub fn write_palette(palette_path: &str, palette: &[Color]) -> Result<()> {
+    write_palette_file(palette_path, &PaletteFile { colors: palette.to_vec(), ..PaletteFile::default() })
+}
+
+/// Writes `palette` in the format given by the file extension, TOML by default.
+///
+/// TOML gets one colour per line and leaves out empty index lists; the other
+/// formats cannot hold `exclude` or `lock`, so those are dropped.
+pub fn write_palette_file(palette_path: &str, palette: &PaletteFile) -> Result<()> {
+    let format = PaletteFormat::from_path(palette_path).unwrap_or(PaletteFormat::Toml);
+    if format == PaletteFormat::Image {
+        let strip = RgbImage::from_fn(palette.colors.len() as u32, 1, |x, _| {
+            let (r, g, b) = palette.colors[x as usize];
+            image::Rgb([r as u8, g as u8, b as u8])
+        });
+        return strip.save(palette_path).map_err(|e| Error::Image(palette_path.to_string(), e));
+    }
+    if format != PaletteFormat::Toml {
+        let name = Path::new(palette_path).file_stem().and_then(|s| s.to_str()).unwrap_or("palette");
+        let bytes = format.encode(name, &palette.colors);
+        return std::fs::write(palette_path, bytes).map_err(|e| Error::Io(palette_path.to_string(), e));
+    }
+
+    let mut toml_str = String::from("palette = [\n");
+    for &(r, g, b) in &palette.colors {
+        toml_str.push_str(&format!("    [{:3}, {:3}, {:3}],\n", r, g, b));
+    }
+    toml_str.push_str("]\n");
+    for (key, indices) in [("exclude", &palette.exclude), ("lock", &palette.lock)] {
+        if !indices.is_empty() {
+            let indices: Vec<String> = indices.iter().map(|i| i.to_string()).collect();
+            toml_str.push_str(&format!("{} = [{}]\n", key, indices.join(", ")));
+        }
+    }
+    std::fs::write(palette_path, toml_str).map_err(|e| Error::Io(palette_path.to_string(), e))
+}
@@ -0,0 +1,330 @@
+//! Image preparation applied to `image_org` before dithering.
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::palette::Color;
+use crate::Converter;
+
+/// Common fantasy-console screen and sprite sizes, by name.
+pub const PRESETS: &[(&str, u32, u32)] = &[
+    ("pico8", 128, 128),
+    ("pico8-sprite", 8, 8),
+    ("picotron", 480, 270),
+    ("picotron-sprite", 16, 16),
+    ("tic80", 240, 136),
+    ("tic80-sprite", 8, 8),
+];
+
+/// Looks up a size in [`PRESETS`].
+pub fn preset(name: &str) -> Result<(u32, u32)> {
+    PRESETS.iter()
+        .find(|p| p.0 == name)
+        .map(|p| (p.1, p.2))
+        .ok_or_else(|| {
+            let names: Vec<&str> = PRESETS.iter().map(|p| p.0).collect();
+            Error::Invalid(format!("unknown size preset '{}' (expected one of: {})", name, names.join(", ")))
+        })
+}
+
+/// How the source is brought to the target size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Scale to fit inside the target, keeping the aspect ratio, and letterbox the rest.
+    #[default]
+    Fit,
+    /// Scale to cover the target, keeping the aspect ratio, and crop the overflow.
+    Fill,
+    /// Keep the source scale; crop around the centre and letterbox where it is smaller.
+    Crop,
+}
+
+/// Resampling filter used when scaling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    /// Area average when shrinking, nearest neighbour when enlarging.
+    Box,
+    #[default]
+    Triangle,
+    Lanczos3,
+}
+
+/// A resize step for [`Converter::resize`].
+#[derive(Debug, Clone)]
+pub struct Resize {
+    pub width: u32,
+    pub height: u32,
+    pub mode: ResizeMode,
+    pub filter: Filter,
+    pub letterbox: Color,
+}
+
+impl Resize {
+    pub fn new(width: u32, height: u32) -> Resize {
+        Resize { width, height, mode: ResizeMode::default(), filter: Filter::default(), letterbox: (0, 0, 0) }
+    }
+
+    pub fn mode(mut self, mode: ResizeMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn letterbox(mut self, letterbox: Color) -> Self {
+        self.letterbox = letterbox;
+        self
+    }
+
+    /// Applies the resize to `image`.
+    pub fn apply(&self, image: &RgbaImage) -> Result<RgbaImage> {
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::Invalid(format!("invalid target size {}x{}", self.width, self.height)));
+        }
+        let (w, h) = image.dimensions();
+        if w == 0 || h == 0 {
+            return Err(Error::Invalid("no image loaded".to_string()));
+        }
+
+        let scale_x = self.width as f64 / w as f64;
+        let scale_y = self.height as f64 / h as f64;
+        let scale = match self.mode {
+            ResizeMode::Fit => scale_x.min(scale_y),
+            ResizeMode::Fill => scale_x.max(scale_y),
+            ResizeMode::Crop => 1.0,
+        };
+        let sw = ((w as f64 * scale).round() as u32).max(1);
+        let sh = ((h as f64 * scale).round() as u32).max(1);
+        let scaled = if (sw, sh) == (w, h) { image.clone() } else { self.scale(image, sw, sh) };
+
+        // centre the scaled image on the letterbox, cropping whatever sticks out
+        let (r, g, b) = self.letterbox;
+        let background = Rgba([r.clamp(0, 255) as u8, g.clamp(0, 255) as u8, b.clamp(0, 255) as u8, 255]);
+        let mut out = RgbaImage::from_pixel(self.width, self.height, background);
+        let dx = (self.width as i64 - sw as i64) / 2;
+        let dy = (self.height as i64 - sh as i64) / 2;
+        imageops::replace(&mut out, &scaled, dx, dy);
+        Ok(out)
+    }
+
+    fn scale(&self, image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+        let filter = match self.filter {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+            Filter::Box if width <= image.width() && height <= image.height() => {
+                return imageops::thumbnail(image, width, height);
+            }
+            Filter::Box => FilterType::Nearest,
+        };
+        imageops::resize(image, width, height, filter)
+    }
+}
+
+/// Unsharp mask: adds back `amount` times the difference between the image and
+/// a Gaussian blur of it, wherever that difference reaches `threshold`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sharpen {
+    /// Standard deviation of the blur, in pixels.
+    pub radius: f64,
+    pub amount: f64,
+    /// Smallest per-channel difference (`0..=255`) that is sharpened, so flat noise is left alone.
+    pub threshold: u8,
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Sharpen { radius: 1.0, amount: 1.0, threshold: 0 }
+    }
+}
+
+impl Sharpen {
+    /// Sharpens `image` in place; alpha is left untouched.
+    pub fn apply(&self, image: &mut RgbaImage) -> Result<()> {
+        if self.radius <= 0.0 {
+            return Err(Error::Invalid(format!("sharpen radius must be positive, got {}", self.radius)));
+        }
+        let blurred = imageops::blur(image, self.radius as f32);
+        for (p, b) in image.pixels_mut().zip(blurred.pixels()) {
+            for c in 0..3 {
+                let diff = p[c] as f64 - b[c] as f64;
+                if diff.abs() >= self.threshold as f64 {
+                    p[c] = to_u8(p[c] as f64 + diff * self.amount);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Converter {
+    /// Resizes `image_org` to the target size before dithering.
+    pub fn resize(mut self, resize: &Resize) -> Result<Self> {
+        self.image_org = resize.apply(&self.image_org)?;
+        (self.width, self.height) = self.image_org.dimensions();
+        self.discard_result();
+        Ok(self)
+    }
+
+    /// Sharpens `image_org` before dithering.
+    pub fn sharpen(mut self, sharpen: &Sharpen) -> Result<Self> {
+        sharpen.apply(&mut self.image_org)?;
+        self.discard_result();
+        Ok(self)
+    }
+
+    /// Applies tonal adjustments to `image_org` before dithering.
+    pub fn tone(mut self, tone: &Tone) -> Result<Self> {
+        tone.apply(&mut self.image_org)?;
+        self.discard_result();
+        Ok(self)
+    }
+}
+
+/// Tonal adjustments, applied in the order the fields are listed.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tone {
+    /// Stretch each channel so its darkest and brightest 0.5% reach 0 and 255.
+    pub auto_levels: bool,
+    /// Equalise the luminance histogram.
+    pub equalize: bool,
+    /// Added to every channel as a fraction of full scale, `-1.0..=1.0`.
+    pub brightness: f64,
+    /// Scales distance from mid-grey; 1.0 leaves the image unchanged.
+    pub contrast: f64,
+    /// Values above 1.0 lift the mid-tones, below 1.0 darken them.
+    pub gamma: f64,
+    /// Scales distance from the pixel's luminance; 0.0 is greyscale.
+    pub saturation: f64,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            auto_levels: false,
+            equalize: false,
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+fn luma(p: &Rgba<u8>) -> f64 {
+    0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64
+}
+
+fn to_u8(v: f64) -> u8 {
+    v.round().clamp(0.0, 255.0) as u8
+}
+
+impl Tone {
+    /// Applies the adjustments to `image` in place. Fully transparent pixels do
+    /// not count towards the histograms.
+    pub fn apply(&self, image: &mut RgbaImage) -> Result<()> {
+        if self.gamma <= 0.0 {
+            return Err(Error::Invalid(format!("gamma must be positive, got {}", self.gamma)));
+        }
+        if self.auto_levels {
+            auto_levels(image);
+        }
+        if self.equalize {
+            equalize(image);
+        }
+
+        let curve: Vec<u8> = (0..256)
+            .map(|v| {
+                let v = v as f64 + self.brightness * 255.0;
+                let v = (v - 127.5) * self.contrast + 127.5;
+                let v = 255.0 * (v.clamp(0.0, 255.0) / 255.0).powf(1.0 / self.gamma);
+                to_u8(v)
+            })
+            .collect();
+        for p in image.pixels_mut() {
+            for c in 0..3 {
+                p[c] = curve[p[c] as usize];
+            }
+            if self.saturation != 1.0 {
+                let y = luma(p);
+                for c in 0..3 {
+                    p[c] = to_u8(y + (p[c] as f64 - y) * self.saturation);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn auto_levels(image: &mut RgbaImage) {
+    let mut histograms = [[0usize; 256]; 3];
+    let mut count = 0;
+    for p in image.pixels().filter(|p| p[3] > 0) {
+        for c in 0..3 {
+            histograms[c][p[c] as usize] += 1;
+        }
+        count += 1;
+    }
+    let clip = count / 200;
+
+    let mut curves = [[0u8; 256]; 3];
+    for (histogram, curve) in histograms.iter().zip(curves.iter_mut()) {
+        let percentile = |from_top: bool| {
+            let mut seen = 0;
+            for i in 0..256 {
+                let v = if from_top { 255 - i } else { i };
+                seen += histogram[v];
+                if seen > clip {
+                    return v;
+                }
+            }
+            if from_top { 255 } else { 0 }
+        };
+        let (low, high) = (percentile(false), percentile(true));
+        for (v, out) in curve.iter_mut().enumerate() {
+            *out = if high > low {
+                to_u8((v as f64 - low as f64) * 255.0 / (high - low) as f64)
+            } else {
+                v as u8
+            };
+        }
+    }
+    for p in image.pixels_mut() {
+        for c in 0..3 {
+            p[c] = curves[c][p[c] as usize];
+        }
+    }
+}
+
+// equalises luminance and shifts each channel by the same amount to keep the hue
+fn equalize(image: &mut RgbaImage) {
+    let mut histogram = [0usize; 256];
+    for p in image.pixels().filter(|p| p[3] > 0) {
+        histogram[to_u8(luma(p)) as usize] += 1;
+    }
+    let total: usize = histogram.iter().sum();
+    if total == 0 {
+        return;
+    }
+    let mut curve = [0.0; 256];
+    let mut cdf = 0;
+    for (v, out) in curve.iter_mut().enumerate() {
+        cdf += histogram[v];
+        *out = cdf as f64 * 255.0 / total as f64;
+    }
+    for p in image.pixels_mut() {
+        let y = luma(p);
+        let shift = curve[to_u8(y) as usize] - y;
+        for c in 0..3 {
+            p[c] = to_u8(p[c] as f64 + shift);
+        }
+    }
+}
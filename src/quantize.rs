@@ -0,0 +1,670 @@
+//! Palette generation from the colours of `image_org`.
+
+use std::collections::HashMap;
+
+use image::RgbaImage;
+
+use crate::error::{Error, Result};
+use crate::palette::Color;
+use crate::rng::SplitMix64;
+use crate::Converter;
+
+/// Algorithm that builds the initial palette.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quantizer {
+    /// Repeatedly split the box with the widest channel range at its median.
+    MedianCut,
+    /// Merge the least populated leaves of a colour octree.
+    Octree,
+    /// Xiaolin Wu's variance-minimising box splits.
+    #[default]
+    Wu,
+    /// k-means++ seeding followed by k-means refinement.
+    KMeans,
+}
+
+/// Settings for [`Converter::generate_palette`].
+#[derive(Debug, Clone)]
+pub struct PaletteGen {
+    pub colors: usize,
+    pub quantizer: Quantizer,
+    /// k-means passes run over the initial palette; 0 keeps it as built.
+    pub refine: usize,
+    /// Seed for k-means++ seeding and for re-seeding empty clusters.
+    pub seed: u64,
+}
+
+impl PaletteGen {
+    pub fn new(colors: usize) -> PaletteGen {
+        PaletteGen { colors, quantizer: Quantizer::default(), refine: 0, seed: 0 }
+    }
+
+    pub fn quantizer(mut self, quantizer: Quantizer) -> Self {
+        self.quantizer = quantizer;
+        self
+    }
+
+    pub fn refine(mut self, refine: usize) -> Self {
+        self.refine = refine;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Builds a palette of at most `colors` entries from the pixels of `image`
+    /// with alpha at or above `alpha_threshold`.
+    ///
+    /// The palette starts with `locked`, unchanged; the remaining entries are
+    /// generated, and k-means refinement moves them around the locked ones.
+    pub fn generate(&self, image: &RgbaImage, alpha_threshold: u8, locked: &[Color]) -> Result<Vec<Color>> {
+        if self.colors == 0 {
+            return Err(Error::Invalid("palette size must be at least 1".to_string()));
+        }
+        if locked.len() > self.colors {
+            return Err(Error::Invalid(format!(
+                "{} locked entries do not fit in a {}-colour palette",
+                locked.len(),
+                self.colors
+            )));
+        }
+        let histogram = histogram(image, alpha_threshold);
+        if histogram.is_empty() {
+            return Err(Error::Invalid("image has no opaque pixels to build a palette from".to_string()));
+        }
+
+        let fixed: Vec<[f64; 3]> = locked.iter().map(|&(r, g, b)| [r as f64, g as f64, b as f64]).collect();
+        let free = self.colors - locked.len();
+        let mut centres = fixed.clone();
+        if histogram.len() <= free {
+            centres.extend(histogram.iter().map(|e| e.0));
+        } else if free > 0 {
+            let mut rng = SplitMix64(self.seed);
+            centres.extend(match self.quantizer {
+                Quantizer::MedianCut => median_cut(&histogram, free),
+                Quantizer::Octree => octree(&histogram, free),
+                Quantizer::Wu => wu(&histogram, free),
+                Quantizer::KMeans => kmeans_seed(&histogram, &fixed, free, &mut rng),
+            });
+            let passes = match self.quantizer {
+                Quantizer::KMeans => self.refine.max(1),
+                _ => self.refine,
+            };
+            kmeans(&histogram, &mut centres, fixed.len(), passes, &mut rng);
+        }
+
+        let mut palette = locked.to_vec();
+        for c in &centres[fixed.len()..] {
+            let color = to_color([c[0].round(), c[1].round(), c[2].round()]);
+            if !palette.contains(&color) {
+                palette.push(color);
+            }
+        }
+        Ok(palette)
+    }
+}
+
+impl Converter {
+    /// Replaces `palette` with one generated from `image_org`, keeping the
+    /// `lock`ed entries at the front.
+    ///
+    /// When the image has transparent pixels, the `transparent_index` entry is
+    /// kept as well, locked and excluded so opaque pixels never take it.
+    pub fn generate_palette(mut self, gen: &PaletteGen) -> Result<Self> {
+        let mut reserved = self.lock.clone();
+        let transparent = self.transparent_index;
+        let reserve_transparent = self.has_transparent_pixels() && !reserved.contains(&transparent);
+        if reserve_transparent {
+            if transparent >= self.palette.len() {
+                return Err(Error::Invalid(format!(
+                    "transparent index {} is outside the {}-colour palette",
+                    transparent,
+                    self.palette.len()
+                )));
+            }
+            reserved.push(transparent);
+        }
+        let locked: Vec<Color> = reserved.iter()
+            .map(|&i| {
+                self.palette.get(i).copied().ok_or_else(|| {
+                    Error::Invalid(format!("locked index {} is outside the {}-colour palette", i, self.palette.len()))
+                })
+            })
+            .collect::<Result<_>>()?;
+        self.palette = gen.generate(&self.image_org, self.alpha_threshold, &locked)?;
+        self.discard_result();
+        self.exclude = (0..reserved.len())
+            .filter(|&i| self.exclude.contains(&reserved[i]) || (reserve_transparent && reserved[i] == transparent))
+            .collect();
+        self.lock = (0..reserved.len()).collect();
+        // without transparent pixels the index goes unused
+        self.transparent_index = reserved.iter().position(|&i| i == transparent).unwrap_or(0);
+        Ok(self)
+    }
+}
+
+// unique opaque colours with their pixel counts, in first-seen scan order
+fn histogram(image: &RgbaImage, alpha_threshold: u8) -> Vec<([f64; 3], f64)> {
+    let mut seen: HashMap<[u8; 3], usize> = HashMap::new();
+    let mut histogram = Vec::new();
+    for p in image.pixels() {
+        if p[3] < alpha_threshold {
+            continue;
+        }
+        let c = [p[0], p[1], p[2]];
+        let i = *seen.entry(c).or_insert_with(|| {
+            histogram.push(([c[0] as f64, c[1] as f64, c[2] as f64], 0.0));
+            histogram.len() - 1
+        });
+        histogram[i].1 += 1.0;
+    }
+    histogram
+}
+
+fn to_color(c: [f64; 3]) -> Color {
+    (c[0] as i32, c[1] as i32, c[2] as i32)
+}
+
+fn distance2(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn nearest(centres: &[[f64; 3]], c: &[f64; 3]) -> (usize, f64) {
+    centres.iter()
+        .enumerate()
+        .map(|(i, centre)| (i, distance2(centre, c)))
+        .fold((0, f64::INFINITY), |best, d| if d.1 < best.1 { d } else { best })
+}
+
+fn accumulate(sum: &mut [f64; 3], c: &[f64; 3], n: f64) {
+    for (s, v) in sum.iter_mut().zip(c) {
+        *s += v * n;
+    }
+}
+
+// count-weighted mean of a set of histogram entries
+fn mean<'a>(colors: impl Iterator<Item = &'a ([f64; 3], f64)>) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    let mut count = 0.0;
+    for (c, n) in colors {
+        accumulate(&mut sum, c, *n);
+        count += n;
+    }
+    [sum[0] / count, sum[1] / count, sum[2] / count]
+}
+
+fn median_cut(histogram: &[([f64; 3], f64)], colors: usize) -> Vec<[f64; 3]> {
+    // widest channel and its range
+    let widest = |entries: &[([f64; 3], f64)]| {
+        (0..3)
+            .map(|ch| {
+                let (lo, hi) = entries.iter()
+                    .fold((f64::MAX, f64::MIN), |(lo, hi), e| (lo.min(e.0[ch]), hi.max(e.0[ch])));
+                (ch, hi - lo)
+            })
+            .fold((0, -1.0), |best, r| if r.1 > best.1 { r } else { best })
+    };
+
+    let mut boxes = vec![histogram.to_vec()];
+    while boxes.len() < colors {
+        let Some((i, ch)) = boxes.iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest(b)))
+            .max_by(|a, b| a.1 .1.total_cmp(&b.1 .1))
+            .map(|(i, (ch, _))| (i, ch))
+        else {
+            break;
+        };
+
+        let mut entries = boxes.swap_remove(i);
+        entries.sort_by(|a, b| a.0[ch].total_cmp(&b.0[ch]));
+        let half = entries.iter().map(|e| e.1).sum::<f64>() / 2.0;
+        let mut count = 0.0;
+        let mut split = 1;
+        for (j, e) in entries.iter().enumerate() {
+            count += e.1;
+            if count >= half {
+                split = (j + 1).clamp(1, entries.len() - 1);
+                break;
+            }
+        }
+        let upper = entries.split_off(split);
+        boxes.push(entries);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|b| mean(b.iter())).collect()
+}
+
+const OCTREE_DEPTH: usize = 8;
+
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    count: f64,
+    sum: [f64; 3],
+    leaf: bool,
+}
+
+fn octree(histogram: &[([f64; 3], f64)], colors: usize) -> Vec<[f64; 3]> {
+    let new_node = |leaf| OctreeNode { children: [None; 8], count: 0.0, sum: [0.0; 3], leaf };
+    let mut nodes = vec![new_node(false)];
+    // internal nodes at each depth, candidates for merging
+    let mut levels: Vec<Vec<usize>> = vec![vec![0]; 1];
+    levels.resize(OCTREE_DEPTH, Vec::new());
+    let mut leaves = 0;
+
+    for &(c, n) in histogram {
+        let rgb = [c[0] as u8, c[1] as u8, c[2] as u8];
+        let mut node = 0;
+        for depth in 0..=OCTREE_DEPTH {
+            nodes[node].count += n;
+            accumulate(&mut nodes[node].sum, &c, n);
+            if depth == OCTREE_DEPTH {
+                break;
+            }
+            let shift = 7 - depth;
+            let child = (((rgb[0] >> shift) & 1) << 2 | ((rgb[1] >> shift) & 1) << 1 | ((rgb[2] >> shift) & 1)) as usize;
+            node = match nodes[node].children[child] {
+                Some(next) => next,
+                None => {
+                    let leaf = depth + 1 == OCTREE_DEPTH;
+                    nodes.push(new_node(leaf));
+                    let next = nodes.len() - 1;
+                    nodes[node].children[child] = Some(next);
+                    if leaf {
+                        leaves += 1;
+                    } else {
+                        levels[depth + 1].push(next);
+                    }
+                    next
+                }
+            };
+        }
+    }
+
+    // fold the least populated deepest node into a single leaf until few enough remain
+    while leaves > colors {
+        let Some(level) = levels.iter_mut().rev().find(|l| !l.is_empty()) else {
+            break;
+        };
+        let (i, _) = level.iter()
+            .enumerate()
+            .min_by(|a, b| nodes[*a.1].count.total_cmp(&nodes[*b.1].count))
+            .unwrap();
+        let node = level[i];
+        let mut children: Vec<usize> = nodes[node].children.iter().flatten().copied().collect();
+        if leaves - (children.len() - 1) < colors {
+            // folding every child would leave too few; fold only the least populated ones together
+            children.sort_by(|&a, &b| nodes[a].count.total_cmp(&nodes[b].count));
+            let (kept, folded) = (children[0], &children[1..leaves - colors + 1]);
+            for &child in folded {
+                let (count, sum) = (nodes[child].count, nodes[child].sum);
+                nodes[kept].count += count;
+                for (s, v) in nodes[kept].sum.iter_mut().zip(sum) {
+                    *s += v;
+                }
+                for slot in nodes[node].children.iter_mut().filter(|slot| **slot == Some(child)) {
+                    *slot = None;
+                }
+            }
+            leaves = colors;
+            break;
+        }
+        level.swap_remove(i);
+        let children = children.len();
+        nodes[node].children = [None; 8];
+        nodes[node].leaf = true;
+        leaves -= children - 1;
+    }
+
+    let mut centres = Vec::with_capacity(leaves);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.leaf {
+            centres.push([node.sum[0] / node.count, node.sum[1] / node.count, node.sum[2] / node.count]);
+        } else {
+            stack.extend(node.children.iter().rev().flatten());
+        }
+    }
+    centres
+}
+
+// Wu's quantiser works on a 33^3 grid of cumulative moments (5 bits per channel plus a zero border).
+const WU_SIZE: usize = 33;
+
+#[derive(Clone, Copy, Default)]
+struct WuBox {
+    r0: usize,
+    r1: usize,
+    g0: usize,
+    g1: usize,
+    b0: usize,
+    b1: usize,
+}
+
+struct Moments {
+    weight: Vec<f64>,
+    r: Vec<f64>,
+    g: Vec<f64>,
+    b: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+fn wu_index(r: usize, g: usize, b: usize) -> usize {
+    (r * WU_SIZE + g) * WU_SIZE + b
+}
+
+impl Moments {
+    fn new(histogram: &[([f64; 3], f64)]) -> Moments {
+        let len = WU_SIZE * WU_SIZE * WU_SIZE;
+        let mut m = Moments {
+            weight: vec![0.0; len],
+            r: vec![0.0; len],
+            g: vec![0.0; len],
+            b: vec![0.0; len],
+            squares: vec![0.0; len],
+        };
+        for &(c, n) in histogram {
+            let cell = |v: f64| (v as usize >> 3) + 1;
+            let i = wu_index(cell(c[0]), cell(c[1]), cell(c[2]));
+            m.weight[i] += n;
+            m.r[i] += c[0] * n;
+            m.g[i] += c[1] * n;
+            m.b[i] += c[2] * n;
+            m.squares[i] += (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]) * n;
+        }
+        for moment in [&mut m.weight, &mut m.r, &mut m.g, &mut m.b, &mut m.squares] {
+            for r in 1..WU_SIZE {
+                let mut area = [0.0; WU_SIZE];
+                for g in 1..WU_SIZE {
+                    let mut line = 0.0;
+                    for b in 1..WU_SIZE {
+                        let i = wu_index(r, g, b);
+                        line += moment[i];
+                        area[b] += line;
+                        moment[i] = moment[wu_index(r - 1, g, b)] + area[b];
+                    }
+                }
+            }
+        }
+        m
+    }
+}
+
+// sum of a cumulative moment over a box
+fn volume(c: &WuBox, m: &[f64]) -> f64 {
+    m[wu_index(c.r1, c.g1, c.b1)] - m[wu_index(c.r1, c.g1, c.b0)] - m[wu_index(c.r1, c.g0, c.b1)]
+        + m[wu_index(c.r1, c.g0, c.b0)] - m[wu_index(c.r0, c.g1, c.b1)] + m[wu_index(c.r0, c.g1, c.b0)]
+        + m[wu_index(c.r0, c.g0, c.b1)] - m[wu_index(c.r0, c.g0, c.b0)]
+}
+
+// the part of `volume` that does not depend on the cut position along `axis`
+fn bottom(c: &WuBox, axis: usize, m: &[f64]) -> f64 {
+    match axis {
+        0 => -m[wu_index(c.r0, c.g1, c.b1)] + m[wu_index(c.r0, c.g1, c.b0)] + m[wu_index(c.r0, c.g0, c.b1)]
+            - m[wu_index(c.r0, c.g0, c.b0)],
+        1 => -m[wu_index(c.r1, c.g0, c.b1)] + m[wu_index(c.r1, c.g0, c.b0)] + m[wu_index(c.r0, c.g0, c.b1)]
+            - m[wu_index(c.r0, c.g0, c.b0)],
+        _ => -m[wu_index(c.r1, c.g1, c.b0)] + m[wu_index(c.r1, c.g0, c.b0)] + m[wu_index(c.r0, c.g1, c.b0)]
+            - m[wu_index(c.r0, c.g0, c.b0)],
+    }
+}
+
+// the part of `volume` at cut position `pos` along `axis`
+fn top(c: &WuBox, axis: usize, pos: usize, m: &[f64]) -> f64 {
+    match axis {
+        0 => m[wu_index(pos, c.g1, c.b1)] - m[wu_index(pos, c.g1, c.b0)] - m[wu_index(pos, c.g0, c.b1)]
+            + m[wu_index(pos, c.g0, c.b0)],
+        1 => m[wu_index(c.r1, pos, c.b1)] - m[wu_index(c.r1, pos, c.b0)] - m[wu_index(c.r0, pos, c.b1)]
+            + m[wu_index(c.r0, pos, c.b0)],
+        _ => m[wu_index(c.r1, c.g1, pos)] - m[wu_index(c.r1, c.g0, pos)] - m[wu_index(c.r0, c.g1, pos)]
+            + m[wu_index(c.r0, c.g0, pos)],
+    }
+}
+
+fn variance(c: &WuBox, m: &Moments) -> f64 {
+    let (r, g, b) = (volume(c, &m.r), volume(c, &m.g), volume(c, &m.b));
+    volume(c, &m.squares) - (r * r + g * g + b * b) / volume(c, &m.weight)
+}
+
+// best cut along `axis`: returns the between-box score and the cut position
+fn maximize(c: &WuBox, axis: usize, first: usize, last: usize, whole: [f64; 4], m: &Moments) -> (f64, Option<usize>) {
+    let base = [bottom(c, axis, &m.r), bottom(c, axis, &m.g), bottom(c, axis, &m.b), bottom(c, axis, &m.weight)];
+    let mut best = (0.0, None);
+    for pos in first..last {
+        let half = [
+            base[0] + top(c, axis, pos, &m.r),
+            base[1] + top(c, axis, pos, &m.g),
+            base[2] + top(c, axis, pos, &m.b),
+            base[3] + top(c, axis, pos, &m.weight),
+        ];
+        if half[3] == 0.0 || whole[3] - half[3] == 0.0 {
+            continue;
+        }
+        let rest = [whole[0] - half[0], whole[1] - half[1], whole[2] - half[2], whole[3] - half[3]];
+        let score = (half[0] * half[0] + half[1] * half[1] + half[2] * half[2]) / half[3]
+            + (rest[0] * rest[0] + rest[1] * rest[1] + rest[2] * rest[2]) / rest[3];
+        if score > best.0 {
+            best = (score, Some(pos));
+        }
+    }
+    best
+}
+
+// splits `c` in place, returning the other half, or `None` if it cannot be split
+fn cut(c: &mut WuBox, m: &Moments) -> Option<WuBox> {
+    let whole = [volume(c, &m.r), volume(c, &m.g), volume(c, &m.b), volume(c, &m.weight)];
+    let cuts = [
+        maximize(c, 0, c.r0 + 1, c.r1, whole, m),
+        maximize(c, 1, c.g0 + 1, c.g1, whole, m),
+        maximize(c, 2, c.b0 + 1, c.b1, whole, m),
+    ];
+    let axis = (0..3).fold(0, |best, a| if cuts[a].0 > cuts[best].0 { a } else { best });
+    let pos = cuts[axis].1?;
+
+    let mut other = *c;
+    match axis {
+        0 => (other.r0, c.r1) = (pos, pos),
+        1 => (other.g0, c.g1) = (pos, pos),
+        _ => (other.b0, c.b1) = (pos, pos),
+    }
+    Some(other)
+}
+
+fn wu(histogram: &[([f64; 3], f64)], colors: usize) -> Vec<[f64; 3]> {
+    let m = Moments::new(histogram);
+    let mut boxes = vec![WuBox { r1: WU_SIZE - 1, g1: WU_SIZE - 1, b1: WU_SIZE - 1, ..WuBox::default() }];
+    let mut scores = vec![0.0];
+    let mut next = 0;
+    while boxes.len() < colors {
+        match cut(&mut boxes[next], &m) {
+            Some(other) => {
+                let score = |c: &WuBox| if volume(c, &m.weight) > 1.0 { variance(c, &m) } else { 0.0 };
+                scores[next] = score(&boxes[next]);
+                scores.push(score(&other));
+                boxes.push(other);
+            }
+            None => scores[next] = 0.0,
+        }
+        next = (0..boxes.len()).fold(0, |best, i| if scores[i] > scores[best] { i } else { best });
+        if scores[next] <= 0.0 {
+            break;
+        }
+    }
+    boxes.iter()
+        .map(|c| {
+            let w = volume(c, &m.weight);
+            [volume(c, &m.r) / w, volume(c, &m.g) / w, volume(c, &m.b) / w]
+        })
+        .collect()
+}
+
+// index of a histogram entry drawn with probability proportional to `weights`
+fn pick(weights: &[f64], rng: &mut SplitMix64) -> usize {
+    let total: f64 = weights.iter().sum();
+    let mut target = rng.next_f64() * total;
+    for (i, &w) in weights.iter().enumerate() {
+        if target < w {
+            return i;
+        }
+        target -= w;
+    }
+    weights.iter().rposition(|&w| w > 0.0).unwrap_or(0)
+}
+
+// k-means++: each new centre is drawn weighted by its squared distance to the nearest
+// existing one, `fixed` included; only the `colors` new centres are returned
+fn kmeans_seed(histogram: &[([f64; 3], f64)], fixed: &[[f64; 3]], colors: usize, rng: &mut SplitMix64) -> Vec<[f64; 3]> {
+    let mut centres = Vec::with_capacity(colors);
+    let mut weights: Vec<f64> = histogram.iter()
+        .map(|e| if fixed.is_empty() { e.1 } else { nearest(fixed, &e.0).1 * e.1 })
+        .collect();
+    // with nothing fixed the first draw is weighted by pixel count alone
+    let mut counts_only = fixed.is_empty();
+    while centres.len() < colors {
+        let centre = histogram[pick(&weights, rng)].0;
+        for (w, e) in weights.iter_mut().zip(histogram) {
+            let d = distance2(&e.0, &centre) * e.1;
+            *w = if counts_only { d } else { w.min(d) };
+        }
+        counts_only = false;
+        centres.push(centre);
+    }
+    centres
+}
+
+// Lloyd iterations; the first `fixed` centres attract colours but never move, and
+// a centre left without colours moves to the worst-served one
+fn kmeans(histogram: &[([f64; 3], f64)], centres: &mut [[f64; 3]], fixed: usize, passes: usize, rng: &mut SplitMix64) {
+    for _ in 0..passes {
+        let mut sums = vec![([0.0; 3], 0.0); centres.len()];
+        let mut errors = Vec::with_capacity(histogram.len());
+        for &(c, n) in histogram {
+            let (i, d) = nearest(centres, &c);
+            accumulate(&mut sums[i].0, &c, n);
+            sums[i].1 += n;
+            errors.push(d * n);
+        }
+
+        let mut moved = false;
+        for (centre, &(sum, n)) in centres.iter_mut().zip(&sums).skip(fixed) {
+            let updated = if n > 0.0 {
+                [sum[0] / n, sum[1] / n, sum[2] / n]
+            } else {
+                let i = pick(&errors, rng);
+                errors[i] = 0.0;
+                histogram[i].0
+            };
+            moved |= distance2(centre, &updated) > 1e-6;
+            *centre = updated;
+        }
+        if !moved {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUANTIZERS: [Quantizer; 4] = [Quantizer::MedianCut, Quantizer::Octree, Quantizer::Wu, Quantizer::KMeans];
+
+    // smooth gradients across all three channels, far more colours than any palette asked for
+    fn gradient() -> RgbaImage {
+        RgbaImage::from_fn(48, 48, |x, y| image::Rgba([(x * 5) as u8, (y * 5) as u8, ((x + y) * 2) as u8, 255]))
+    }
+
+    // `colors` flat bands of well separated colours
+    fn bands(colors: &[Color]) -> RgbaImage {
+        RgbaImage::from_fn(colors.len() as u32 * 4, 4, |x, _| {
+            let (r, g, b) = colors[x as usize / 4];
+            image::Rgba([r as u8, g as u8, b as u8, 255])
+        })
+    }
+
+    #[test]
+    fn fills_the_requested_colours_from_a_rich_image() {
+        for quantizer in QUANTIZERS {
+            for colors in [1, 2, 5, 16, 64] {
+                for refine in [0, 3] {
+                    let palette = PaletteGen::new(colors).quantizer(quantizer).refine(refine).generate(&gradient(), 128, &[]).unwrap();
+                    assert_eq!(palette.len(), colors, "{:?} {} refine {}", quantizer, colors, refine);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn generates_exactly_the_separable_colours() {
+        let source = [(0, 0, 0), (255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 255, 255), (255, 255, 0)];
+        let image = bands(&source);
+        for quantizer in QUANTIZERS {
+            let mut palette = PaletteGen::new(source.len()).quantizer(quantizer).generate(&image, 128, &[]).unwrap();
+            palette.sort();
+            let mut expected = source.to_vec();
+            expected.sort();
+            assert_eq!(palette, expected, "{:?}", quantizer);
+            // asking for more than the image holds yields every colour once
+            assert_eq!(PaletteGen::new(16).quantizer(quantizer).generate(&image, 128, &[]).unwrap().len(), source.len());
+            assert_eq!(PaletteGen::new(3).quantizer(quantizer).generate(&image, 128, &[]).unwrap().len(), 3, "{:?}", quantizer);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_palette() {
+        for quantizer in QUANTIZERS {
+            let gen = PaletteGen::new(12).quantizer(quantizer).refine(4).seed(7);
+            let first = gen.generate(&gradient(), 128, &[(10, 20, 30)]).unwrap();
+            for _ in 0..3 {
+                assert_eq!(gen.generate(&gradient(), 128, &[(10, 20, 30)]).unwrap(), first, "{:?}", quantizer);
+            }
+        }
+    }
+
+    #[test]
+    fn locked_entries_stay_at_the_front_unchanged() {
+        let locked = [(1, 2, 3), (250, 0, 0), (120, 121, 122)];
+        for quantizer in QUANTIZERS {
+            for refine in [0, 5] {
+                let palette = PaletteGen::new(8).quantizer(quantizer).refine(refine).generate(&gradient(), 128, &locked).unwrap();
+                assert_eq!(palette[..locked.len()], locked, "{:?} refine {}", quantizer, refine);
+                assert!(palette.len() <= 8);
+            }
+            // a palette of only locked entries generates nothing
+            let palette = PaletteGen::new(3).quantizer(quantizer).generate(&gradient(), 128, &locked).unwrap();
+            assert_eq!(palette, locked);
+        }
+    }
+
+    #[test]
+    fn reserves_the_transparent_entry() {
+        let image = RgbaImage::from_fn(16, 4, |x, _| match x {
+            0..=3 => image::Rgba([0, 0, 0, 0]),
+            _ => image::Rgba([(x * 16) as u8, 100, 50, 255]),
+        });
+        let palette = vec![(0, 0, 0), (255, 0, 255), (1, 1, 1)];
+        let mut con = Converter::from_parts(palette, Vec::new()).transparent_index(1).lock(vec![2]);
+        (con.width, con.height) = image.dimensions();
+        con.image_org = image;
+
+        let con = con.generate_palette(&PaletteGen::new(4)).unwrap();
+        assert_eq!(con.palette[..2], [(1, 1, 1), (255, 0, 255)]);
+        assert_eq!((con.transparent_index, con.lock.clone(), con.exclude.clone()), (1, vec![0, 1], vec![1]));
+        let con = con.bayer().unwrap();
+        for (i, &index) in con.indexed.iter().enumerate() {
+            assert_eq!(index == 1, i % 16 < 4, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn rejects_impossible_requests() {
+        assert!(PaletteGen::new(0).generate(&gradient(), 128, &[]).is_err());
+        assert!(PaletteGen::new(1).generate(&gradient(), 128, &[(0, 0, 0), (1, 1, 1)]).is_err());
+        let transparent = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 0]));
+        assert!(PaletteGen::new(4).generate(&transparent, 128, &[]).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::dither::{Buffer, Ditherer, Pixel};
+use crate::error::{Error, Result};
+use crate::palette::Color;
+use crate::Converter;
+
+// rounds of single swaps tried after the greedy build
+const SWAP_PASSES: usize = 4;
+
+impl Converter {
+    /// Narrows `palette` to the `k` entries whose dithered result with `ditherer`
+    /// looks closest to `image_org`, and records their loaded indices in `subset`.
+    ///
+    /// `lock`ed entries are always kept, and so is `transparent_index` when the
+    /// image has transparent pixels; excluded ones are only kept when locked. The rest are added greedily and then improved by swapping one at
+    /// a time; every candidate is a full dither, so this is slow on large images.
+    /// A chosen entry whose index is below `k` keeps it as its slot, which keeps
+    /// PICO-8's `pal()` remap as short as possible.
+    pub fn best_subset<D: Ditherer + ?Sized>(mut self, k: usize, ditherer: &D) -> Result<Self> {
+        self.check_ready()?;
+        self.discard_result();
+        if k == 0 {
+            return Err(Error::Invalid("subset size must be at least 1".to_string()));
+        }
+        let mut chosen: Vec<usize> = Vec::with_capacity(k);
+        for &i in &self.lock {
+            if !chosen.contains(&i) {
+                chosen.push(i);
+            }
+        }
+        let transparent = self.transparent_index;
+        if self.has_transparent_pixels() && !chosen.contains(&transparent) {
+            chosen.push(transparent);
+        }
+        if chosen.len() > k {
+            return Err(Error::Invalid(format!("{} locked and transparent entries do not fit in a {}-colour subset", chosen.len(), k)));
+        }
+        let locked = chosen.len();
+        let loaded = std::mem::take(&mut self.palette);
+        if k >= loaded.len() {
+            self.palette = loaded;
+            self.subset = (0..self.palette.len()).collect();
+            return Ok(self);
+        }
+
+        let source = Buffer::from_image(&self.image_org, self.linear, self.alpha_threshold);
+        let pixels: Vec<Pixel> = (0..source.r.len()).map(|i| source.get(i)).collect();
+        let target = blur(&source, &pixels);
+
+        let excluded = std::mem::take(&mut self.exclude);
+        let usable = |chosen: &[usize], candidate: usize| !chosen.contains(&candidate) && !excluded.contains(&candidate);
+        let mut best = f64::INFINITY;
+        while chosen.len() < k {
+            let mut pick = None;
+            best = f64::INFINITY;
+            for candidate in 0..loaded.len() {
+                if !usable(&chosen, candidate) {
+                    continue;
+                }
+                chosen.push(candidate);
+                let error = self.subset_error(ditherer, &loaded, &excluded, transparent, &chosen, &target);
+                chosen.pop();
+                if error < best {
+                    (best, pick) = (error, Some(candidate));
+                }
+            }
+            match pick {
+                Some(candidate) => chosen.push(candidate),
+                None => break,
+            }
+        }
+
+        for _ in 0..SWAP_PASSES {
+            let mut improved = false;
+            for slot in locked..chosen.len() {
+                for candidate in 0..loaded.len() {
+                    if !usable(&chosen, candidate) {
+                        continue;
+                    }
+                    let previous = std::mem::replace(&mut chosen[slot], candidate);
+                    let error = self.subset_error(ditherer, &loaded, &excluded, transparent, &chosen, &target);
+                    if error < best {
+                        best = error;
+                        improved = true;
+                    } else {
+                        chosen[slot] = previous;
+                    }
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+
+        self.subset = slots(&chosen, chosen.len());
+        self.palette = self.subset.iter().map(|&i| loaded[i]).collect();
+        self.exclude = (0..self.subset.len()).filter(|&i| excluded.contains(&self.subset[i])).collect();
+        self.lock = (0..self.subset.len()).filter(|&i| self.lock.contains(&self.subset[i])).collect();
+        // without transparent pixels the entry may have been dropped, and the index goes unused
+        self.transparent_index = self.subset.iter().position(|&i| i == transparent).unwrap_or(0);
+        Ok(self)
+    }
+
+    // squared difference between the blurred source and the blurred dither with the `chosen` entries
+    fn subset_error<D: Ditherer + ?Sized>(
+        &mut self,
+        ditherer: &D,
+        loaded: &[Color],
+        excluded: &[usize],
+        transparent: usize,
+        chosen: &[usize],
+        target: &[Pixel],
+    ) -> f64 {
+        self.palette = chosen.iter().map(|&i| loaded[i]).collect();
+        self.exclude = (0..chosen.len()).filter(|&i| excluded.contains(&chosen[i])).collect();
+        self.transparent_index = chosen.iter().position(|&i| i == transparent).unwrap_or(0);
+        let buf = self.run(ditherer);
+        let pixels: Vec<Pixel> = (0..buf.indices.len())
+            .map(|i| if buf.transparent[i] { (0.0, 0.0, 0.0) } else { buf.from_color(self.palette[buf.indices[i]]) })
+            .collect();
+        blur(&buf, &pixels)
+            .iter()
+            .zip(target)
+            .zip(&buf.transparent)
+            .filter(|(_, &transparent)| !transparent)
+            .map(|((a, b), _)| (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2))
+            .sum()
+    }
+}
+
+// 3x3 box blur over opaque pixels, roughly how a dither reads at a normal viewing distance
+fn blur(buf: &Buffer, pixels: &[Pixel]) -> Vec<Pixel> {
+    let (w, h) = (buf.width as i32, buf.height as i32);
+    let mut out = Vec::with_capacity(pixels.len());
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = (0.0, 0.0, 0.0);
+            let mut count = 0.0;
+            for (nx, ny) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))) {
+                if nx < 0 || nx >= w || ny < 0 || ny >= h {
+                    continue;
+                }
+                let i = buf.idx(nx as u32, ny as u32);
+                if !buf.transparent[i] {
+                    sum = (sum.0 + pixels[i].0, sum.1 + pixels[i].1, sum.2 + pixels[i].2);
+                    count += 1.0;
+                }
+            }
+            out.push(if count > 0.0 { (sum.0 / count, sum.1 / count, sum.2 / count) } else { sum });
+        }
+    }
+    out
+}
+
+// orders the chosen entries so those with an index below `k` sit in their own slot
+fn slots(chosen: &[usize], k: usize) -> Vec<usize> {
+    let mut slots: Vec<Option<usize>> = vec![None; k];
+    for &i in chosen.iter().filter(|&&i| i < k) {
+        slots[i] = Some(i);
+    }
+    let mut rest = chosen.iter().filter(|&&i| i >= k).copied();
+    for slot in slots.iter_mut().filter(|s| s.is_none()) {
+        *slot = rest.next();
+    }
+    slots.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::dither::ErrorDiffusion;
+    use crate::kernel::named_kernel;
+
+    const PALETTE: [Color; 6] = [(0, 0, 0), (255, 0, 0), (0, 0, 255), (0, 255, 0), (255, 255, 255), (255, 0, 255)];
+
+    // red and blue halves with a transparent column on the left
+    fn sprite(transparent: bool) -> Converter {
+        let image = RgbaImage::from_fn(8, 4, |x, _| match x {
+            0 if transparent => Rgba([0, 0, 0, 0]),
+            0..=3 => Rgba([250, 10, 10, 255]),
+            _ => Rgba([10, 10, 250, 255]),
+        });
+        let mut con = Converter::from_parts(PALETTE.to_vec(), named_kernel("floyd-steinberg").unwrap());
+        (con.width, con.height) = image.dimensions();
+        con.image_org = image;
+        con
+    }
+
+    #[test]
+    fn keeps_and_remaps_the_transparent_entry() {
+        let ditherer = ErrorDiffusion::new(named_kernel("floyd-steinberg").unwrap());
+        let con = sprite(true).transparent_index(5).best_subset(3, &ditherer).unwrap();
+        assert!(con.subset.contains(&1) && con.subset.contains(&2) && con.subset.contains(&5), "{:?}", con.subset);
+        assert_eq!(con.palette[con.transparent_index], (255, 0, 255));
+
+        let con = con.dither(&ditherer).unwrap();
+        for y in 0..4 {
+            assert_eq!(con.indexed[con.idx(0, y)], con.transparent_index);
+            for x in 1..8 {
+                assert_ne!(con.indexed[con.idx(x, y)], con.transparent_index);
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_entry_is_optional_when_nothing_is_transparent() {
+        let ditherer = ErrorDiffusion::new(named_kernel("floyd-steinberg").unwrap());
+        let con = sprite(false).transparent_index(5).best_subset(2, &ditherer).unwrap();
+        assert_eq!(con.subset.len(), 2);
+        assert!(con.transparent_index < 2);
+        assert!(con.dither(&ditherer).is_ok());
+    }
+
+    #[test]
+    fn transparent_entry_counts_towards_the_subset_size() {
+        let ditherer = ErrorDiffusion::new(named_kernel("floyd-steinberg").unwrap());
+        assert!(sprite(true).transparent_index(5).lock(vec![0]).best_subset(1, &ditherer).is_err());
+    }
+}